@@ -0,0 +1,16 @@
+//! Feed arbitrary input to the parser, it should never panic.
+//!
+//! Run from the repository root with records in `tests/recs` as seeds:
+//! `cargo +nightly fuzz run parse fuzz/corpus/parse tests/recs`
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut parser) = mgx::Parser::new(data) else {
+        return;
+    };
+    let mut rec = mgx::Record::default();
+    let _ = parser.parse_to(&mut rec);
+    let _ = mgx::map_image(&rec, &parser);
+});
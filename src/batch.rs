@@ -0,0 +1,111 @@
+use crate::error::{Error, Result, Section};
+use crate::from_file::from_path;
+use crate::{ParseOptions, Parser, Record};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Records and errors are moved out of worker threads
+const _: fn() = || {
+    fn send<T: Send>() {}
+    send::<Record>();
+    send::<Error>();
+    send::<Parser<Vec<u8>>>();
+};
+
+/// Statistics of a `parse_many()` call
+#[derive(Debug, Default, Clone)]
+pub struct BatchStats {
+    pub files: usize,
+    /// Files returned as `Err`, panicked ones included
+    pub failed: usize,
+    pub panicked: usize,
+    /// Failed files by the section where parsing stopped
+    pub errors: HashMap<Section, usize>,
+    pub threads: usize,
+    /// Wall time of the whole batch
+    pub elapsed: Duration,
+    /// Time spent on all files, summed over threads. Reading files is included
+    pub busy: Duration,
+    /// Index and time of the slowest file
+    pub slowest: Option<(usize, Duration)>,
+}
+
+/// Parse files on a pool of threads, one per available CPU. Records are returned in the order of `paths`, each
+/// filled the same as `from_file()` with `options`. A panic while parsing a file is caught and returned as
+/// `Error::Panicked` for that file, the default panic hook still prints it.
+/// `Record`, `Error` and `Parser<Vec<u8>>` are `Send`, so they can be moved to other threads
+pub fn parse_many<P: AsRef<Path> + Sync>(paths: &[P], options: ParseOptions) -> (Vec<Result<Record>>, BatchStats) {
+    let start = Instant::now();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len()).max(1);
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<Option<(Result<Record>, Duration)>> = paths.iter().map(|_| None).collect();
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(idx) else {
+                            return done;
+                        };
+                        let begin = Instant::now();
+                        let result = parse_one(path.as_ref(), &options);
+                        done.push((idx, result, begin.elapsed()));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            // Panics are caught for each file, workers don't panic
+            for (idx, result, time) in worker.join().unwrap_or_default() {
+                results[idx] = Some((result, time));
+            }
+        }
+    });
+
+    let mut stats = BatchStats { files: paths.len(), threads, ..Default::default() };
+    let records = results
+        .into_iter()
+        .enumerate()
+        .map(|(idx, result)| {
+            let (result, time) =
+                result.unwrap_or_else(|| (Err(panicked("Worker thread stopped".into())), Duration::ZERO));
+            stats.busy += time;
+            if stats.slowest.is_none_or(|(_, slowest)| time > slowest) {
+                stats.slowest = Some((idx, time));
+            }
+            if let Err(e) = &result {
+                stats.failed += 1;
+                stats.panicked += matches!(e, Error::Panicked { .. }) as usize;
+                *stats.errors.entry(e.section()).or_default() += 1;
+            }
+            result
+        })
+        .collect();
+    stats.elapsed = start.elapsed();
+    (records, stats)
+}
+
+fn parse_one(path: &Path, options: &ParseOptions) -> Result<Record> {
+    match catch_unwind(AssertUnwindSafe(|| from_path(path, options.clone()))) {
+        Ok(result) => result.map(|(record, _)| record),
+        Err(payload) => {
+            let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+                (Some(x), _) => x.to_string(),
+                (_, Some(x)) => x.clone(),
+                _ => "Unknown panic".to_string(),
+            };
+            Err(panicked(message))
+        }
+    }
+}
+
+fn panicked(message: String) -> Error {
+    Error::Panicked { section: Section::Unknown, offset: 0, message }
+}
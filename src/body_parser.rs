@@ -1,45 +1,137 @@
 use crate::cursor::StreamCursor;
+use crate::error::{Error, Result, Section, Warning};
+use crate::events::CommandKind;
+use crate::parser::ParseOptions;
+use crate::profile::VersionProfile;
 use crate::record::Chat;
 use crate::record::Record;
-use crate::record::Version;
 use crate::val;
-use anyhow::{bail, Ok, Result};
-
-pub fn parse_body<T: AsRef<[u8]>>(b: &mut StreamCursor<T>, r: &mut Record) -> Result<()> {
-    const OP_COMMAND: i32 = 0x01;
-    const OP_SYNC: i32 = 0x02;
-    const OP_VIEWLOCK: i32 = 0x03;
-    const OP_CHAT: i32 = 0x04;
-
-    const COMMAND_RESIGN: u8 = 0x0b;
-    const COMMAND_RESEARCH: u8 = 0x65;
-    const COMMAND_TRAIN: u8 = 0x77;
-    const COMMAND_TRAIN_SINGLE: u8 = 0x64;
-    const COMMAND_BUILD: u8 = 0x66;
-    const COMMAND_TRIBUTE: u8 = 0x6c;
-    const COMMAND_POSTGAME: u8 = 0xff;
-    const COMMAND_MOVE: u8 = 0x03;
-    const COMMAND_SAVE: u8 = 0x1b;
-    const COMMAND_CHAPTER: u8 = 0x20;
-
-    debug_assert!({
-        if b.remain() >= 4 {
-            val!(b.peek_i32()) == OP_SYNC
-        } else {
-            true
-        }
-    });
+use crate::visitor::{command_player, BodyVisitor};
+use std::ops::Range;
+
+pub(crate) const OP_COMMAND: i32 = 0x01;
+pub(crate) const OP_SYNC: i32 = 0x02;
+pub(crate) const OP_VIEWLOCK: i32 = 0x03;
+pub(crate) const OP_CHAT: i32 = 0x04;
+pub(crate) const OP_POSTGAME: i32 = 0x06;
+
+/// Commands longer than this are treated as garbage when resynchronizing
+const MAX_CMD_LEN: usize = 0x10000;
+/// View lock coordinates beyond this are not on any map
+const MAX_VIEW_COORD: f32 = 1000.0;
+/// Number of consecutive valid operations required to resume parsing
+const RESYNC_CHAIN: usize = 3;
+/// Data kept after `stop` of a window, enough to check `RESYNC_CHAIN` operations of the maximum length
+pub(crate) const WINDOW_MARGIN: usize = (RESYNC_CHAIN + 1) * (MAX_CMD_LEN + 12);
+
+/// Where parsing of a window of body stops
+pub(crate) enum WindowEnd {
+    /// Operations before `stop` are parsed, continue from the cursor
+    Next,
+    /// A command runs past the window, continue from this position in the file
+    Skip(usize),
+    /// Postgame operation of DE, the rest of body is not parsed
+    Done,
+}
 
-    while b.remain() >= 8 {
+/// Parse operations in body. Offsets of errors are positions in the file
+pub fn parse_body<T: AsRef<[u8]>>(
+    b: &mut StreamCursor<T>,
+    r: &mut Record,
+    options: &ParseOptions,
+    visitor: &mut dyn BodyVisitor,
+) -> Result<()> {
+    check_first_op(b, options)?;
+    parse_window(b, r, options, visitor, b.data().len(), true).map(|_| ())
+}
+
+/// Body starts with a sync operation
+pub(crate) fn check_first_op<T: AsRef<[u8]>>(b: &StreamCursor<T>, options: &ParseOptions) -> Result<()> {
+    if let Some(op) = b.peek_i32().filter(|op| options.strict && *op != OP_SYNC) {
+        return Err(Error::BadBodyOp { section: Section::Body, offset: b.filepos(b.tell()), op });
+    }
+    Ok(())
+}
+
+/// Parse operations starting before `stop` in a window of body read from `io::Read`. Operations after `stop` are left
+/// for the next window, there should be `WINDOW_MARGIN` bytes after it unless the window is the `last` of a chapter
+pub(crate) fn parse_window<T: AsRef<[u8]>>(
+    b: &mut StreamCursor<T>,
+    r: &mut Record,
+    options: &ParseOptions,
+    visitor: &mut dyn BodyVisitor,
+    stop: usize,
+    last: bool,
+) -> Result<WindowEnd> {
+    parse_ops(b, r, options, visitor, stop, last).map_err(|e| e.locate(Section::Body, b.filepos(b.tell())))
+}
+
+fn parse_ops<T: AsRef<[u8]>>(
+    b: &mut StreamCursor<T>,
+    r: &mut Record,
+    options: &ParseOptions,
+    visitor: &mut dyn BodyVisitor,
+    stop: usize,
+    last: bool,
+) -> Result<WindowEnd> {
+    let profile = r.profile();
+    // Garbage from the previous window
+    if let Some(start) = r.debug.skipfrom.take() {
+        resync(b, r, start, b.tell(), stop, last);
+    }
+
+    while b.tell() < stop {
+        // Stop before an operation cut off by the end of file, `duration` is then the last complete game time
+        let pos = b.tell();
+        let check = check_op(b.data(), pos, profile);
+        if let OpCheck::Truncated = check {
+            r.truncated |= last;
+            break;
+        }
+        r.debug.opcount += 1;
+        if r.debug.opcount > options.limits.body_ops {
+            return Err(Error::LimitExceeded {
+                section: Section::Body,
+                offset: b.filepos(pos),
+                limit: "body operations",
+                max: options.limits.body_ops,
+            });
+        }
         let op_type = val!(b.get_i32());
         match op_type {
             OP_COMMAND => {
-                let cmdlen = val!(b.get_u32()) + 4;
-                let nextpos = if b.remain() < cmdlen as usize { b.data().len() } else { b.tell() + cmdlen as usize };
+                if options.recover && matches!(check, OpCheck::Invalid) {
+                    resync(b, r, b.filepos(pos), pos + 1, stop, last);
+                    continue;
+                }
+                let cmdlen = val!(b.get_u32()) as usize + 4;
+                if !last && b.remain() < cmdlen {
+                    return Ok(WindowEnd::Skip(b.filepos(b.tell() + cmdlen)));
+                }
+                let truncated = b.remain() < cmdlen;
+                let nextpos = if truncated { b.data().len() } else { b.tell() + cmdlen };
 
+                let cmdstart = b.tell();
                 let cmd = val!(b.get_u8());
-                match cmd {
-                    COMMAND_RESIGN => {
+                let kind = CommandKind::from(cmd);
+                if truncated {
+                    // Complete commands are checked before, this is garbage with an unusual length
+                    r.warnings.push(Warning::TruncatedCommand { offset: b.filepos(b.tell() - 1), cmd, len: cmdlen });
+                }
+                if matches!(kind, CommandKind::Order | CommandKind::Move) {
+                    if let Some(count) = b.current().first().and_then(|&slot| r.debug.unitcmds.get_mut(slot as usize)) {
+                        *count += 1;
+                    }
+                }
+                match kind {
+                    CommandKind::Resign if profile.resign_by_index => {
+                        let idx = val!(b.get_i8());
+                        if let Some(slot) = r.players.iter().position(|p| p.isvalid() && p.index == Some(idx as i32)) {
+                            r.players[slot].resigned = Some(r.duration);
+                            visitor.on_resign(r, slot);
+                        }
+                    }
+                    CommandKind::Resign => {
                         // In https://github.com/stefan-kolb/aoc-mgx-format/blob/master/spec/body/actions/0b-resign.md,
                         // player index and slot have wrong order. The first byte is index, second byte is player slot.
                         // https://github.com/goto-bus-stop/recanalyst/blob/master/src/Analyzers/BodyAnalyzer.php is right on this.
@@ -48,141 +140,274 @@ pub fn parse_body<T: AsRef<[u8]>>(b: &mut StreamCursor<T>, r: &mut Record) -> Re
                         if (0..9).contains(&slot) && r.players[slot as usize].isvalid() {
                             r.players[slot as usize].resigned = Some(r.duration);
                             r.players[slot as usize].disconnected = b.get_bool(4);
+                            visitor.on_resign(r, slot as usize);
                         }
                     }
-                    COMMAND_RESEARCH => {
-                        b.mov(7);
+                    CommandKind::Research => {
+                        b.seek(cmdstart + profile.research_player);
                         let idx = val!(b.get_i8());
-                        b.mov(1);
+                        b.seek(cmdstart + 10);
                         let techid = val!(b.get_i16());
-                        
+
                         // Find the slot by matching player index
                         if let Some(slot) = r.players.iter().position(|p| p.index == Some(idx as i32)) {
                             match techid {
-                                101 => r.players[slot].feudaltime = Some(r.duration + 130000),
+                                101 => r.players[slot].feudaltime = Some(r.duration.saturating_add(130000)),
                                 102 => {
                                     if let Some(civ_raw) = r.players[slot].civ_raw {
-                                        r.players[slot].castletime = Some(
-                                            r.duration
-                                                + match civ_raw {
-                                                    8 => 160000 / 1.10 as u32,
-                                                    _ => 160000,
-                                                },
-                                        )
+                                        r.players[slot].castletime = Some(r.duration.saturating_add(match civ_raw {
+                                            8 => 160000 / 1.10 as u32,
+                                            _ => 160000,
+                                        }))
                                     } else {
-                                        r.players[slot].castletime = Some(r.duration + 160000)
+                                        r.players[slot].castletime = Some(r.duration.saturating_add(160000))
                                     }
                                 }
                                 103 => {
                                     if let Some(civ_raw) = r.players[slot].civ_raw {
-                                        r.players[slot].imperialtime = Some(
-                                            r.duration
-                                                + match civ_raw {
-                                                    8 => 190000 / 1.10 as u32,
-                                                    _ => 190000,
-                                                },
-                                        )
+                                        r.players[slot].imperialtime = Some(r.duration.saturating_add(match civ_raw {
+                                            8 => 190000 / 1.10 as u32,
+                                            _ => 190000,
+                                        }))
                                     } else {
-                                        r.players[slot].imperialtime = Some(r.duration + 190000)
+                                        r.players[slot].imperialtime = Some(r.duration.saturating_add(190000))
                                     }
                                 }
                                 _ => {}
                             }
                         }
                     }
-                    COMMAND_TRAIN => {
+                    CommandKind::Train => {
                         // Handle train command
                     }
-                    COMMAND_TRAIN_SINGLE => {
+                    CommandKind::TrainSingle => {
                         // Handle train single command
                     }
-                    COMMAND_BUILD => {
+                    CommandKind::Build => {
                         // Handle build command
                     }
-                    COMMAND_TRIBUTE => {
+                    CommandKind::Tribute => {
                         // Handle tribute command
                     }
-                    COMMAND_POSTGAME => {
+                    CommandKind::Postgame => {
                         // Handle postgame command
                     }
-                    COMMAND_MOVE => {
+                    CommandKind::Move => {
                         const EARLYMOVE_THRESHOLD: usize = 5;
                         const MOVE_CMD_SIZE: usize = 19;
                         if r.debug.earlymovecount < EARLYMOVE_THRESHOLD && b.remain() >= MOVE_CMD_SIZE {
-                            r.debug.earlymovecmd.push(b.current()[..MOVE_CMD_SIZE].try_into()?);
+                            r.debug.earlymovecmd.push(val!(b.current()[..MOVE_CMD_SIZE].try_into().ok()));
                             r.debug.earlymovetime.push(r.duration);
                             r.debug.earlymovecount += 1;
                         }
                     }
-                    COMMAND_SAVE => {
-                        // Handle save command
-                    }
-                    COMMAND_CHAPTER => {
-                        // Handle chapter command
+                    CommandKind::Save | CommandKind::Chapter => {
+                        // Saving issues both commands at the same time
+                        if r.savetimes.last() != Some(&r.duration) {
+                            r.savetimes.push(r.duration);
+                        }
+                        r.debug.savecmdfound |= kind == CommandKind::Save;
                     }
                     _ => {
                         // Handle unknown command
                     }
                 }
 
+                // Command data doesn't include the last 4 bytes
+                let bytes = &b.data()[cmdstart..if truncated { nextpos } else { nextpos - 4 }];
+                let player = command_player(kind, bytes, profile, r);
+                visitor.on_command(r, player, kind, bytes);
                 b.seek(nextpos);
             }
             OP_SYNC => {
                 let time_delta = val!(b.get_i32());
                 if !(0..=1000).contains(&time_delta) {
-                    #[cfg(debug_assertions)]
-                    bail!("Unusual time delta: {} @bodypos: {}", time_delta, b.tell() - 4);
-                    #[allow(unreachable_code)]
+                    if options.strict {
+                        return Err(Error::BadBodyOp { section: Section::Body, offset: b.filepos(pos), op: op_type });
+                    }
+                    // Skipped garbage is reported once as `SkippedBody`
+                    if options.recover {
+                        resync(b, r, b.filepos(pos), pos + 1, stop, last);
+                    } else {
+                        r.warnings.push(Warning::UnusualSyncDelta { offset: b.filepos(pos), delta: time_delta });
+                    }
+                    continue;
+                }
+                r.duration = r.duration.saturating_add(time_delta as u32);
+                visitor.on_sync(r, time_delta as u32);
+                if profile.sync_checksums {
+                    // Checksums follow a zero marker
+                    if b.peek_u32() == Some(0) {
+                        b.mov(360);
+                    }
                     continue;
                 }
-                r.duration += time_delta as u32;
                 let sync_data = val!(b.get_i32());
                 b.mov(if sync_data != 0x03 { 28 } else { 0 });
                 b.mov(12);
             }
+            OP_POSTGAME if profile.postgame => {
+                // Achievements of DE are at the end of body
+                return Ok(WindowEnd::Done);
+            }
             OP_VIEWLOCK => {
                 b.mov(12);
             }
             OP_CHAT => {
                 let command = val!(b.get_i32());
                 if command == 500 {
-                    if r.ver == Some(Version::AoK) || r.ver == Some(Version::AoKTrial) {
-                        b.mov(32);
-                    } else {
-                        b.mov(20);
-                    }
+                    b.mov(profile.chat500_len as isize);
                     continue;
                 }
-                debug_assert_eq!(command, -1);
-                let msg = b.extract_str_l32();
-                if let Some(message) = msg.as_ref() {
-                    if message.len() >= 7
-                        && message.starts_with(b"@#")
-                        && message.ends_with(b"--")
-                        && message[3] == b'-'
-                        && message[4] == b'-'
-                        || message.is_empty()
-                    {
-                        continue;
-                    }
-
-                    r.chat.push(Chat { time: Some(r.duration), player: None, content_raw: msg, content: None });
+                if options.strict && command != -1 {
+                    return Err(Error::BadBodyOp { section: Section::Body, offset: b.filepos(pos + 4), op: op_type });
+                }
+                if options.recover && matches!(check, OpCheck::Invalid) {
+                    resync(b, r, b.filepos(pos), pos + 1, stop, last);
+                    continue;
+                }
+                if let Some(chat) = b.extract_str_l32().and_then(|msg| body_chat(msg, profile, r.duration)) {
+                    r.chat.push(chat);
+                    visitor.on_chat(r, val!(r.chat.last()));
                 }
             }
             _ => {
-                #[cfg(debug_assertions)]
-                if r.ver == Some(Version::AoK)
-                    || r.ver == Some(Version::AoKTrial)
-                    || r.ver == Some(Version::AoC)
-                    || r.ver == Some(Version::AoCTrial)
-                    || r.ver == Some(Version::AoC10a)
-                    || r.ver == Some(Version::AoC10c)
-                {
-                    bail!("Unknown Operation: {} @ {}", op_type, b.tell() - 4);
+                let offset = b.filepos(pos);
+                // Operations of later versions are not fully known
+                if options.strict && profile.known_ops {
+                    return Err(Error::BadBodyOp { section: Section::Body, offset, op: op_type });
+                }
+                if options.recover {
+                    resync(b, r, offset, pos + 1, stop, last);
+                    continue;
+                }
+                match r.warnings.last_mut() {
+                    Some(Warning::UnknownBodyOp { end, .. }) if *end == offset => *end = offset + 4,
+                    _ => r.warnings.push(Warning::UnknownBodyOp { offset, end: offset + 4, op: op_type }),
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(WindowEnd::Next)
+}
+
+/// Skip garbage starting at file position `start`. Move to the first position from `from` where a few consecutive
+/// operations look valid, or to the end of body, and report the skipped range. Positions after `stop` are checked
+/// with the next window
+fn resync<T: AsRef<[u8]>>(b: &mut StreamCursor<T>, r: &mut Record, start: usize, from: usize, stop: usize, last: bool) {
+    let data = b.data();
+    let end = match next_plausible(data, from..stop, r.profile()) {
+        Some(end) => end,
+        None if last => data.len(),
+        None => {
+            r.debug.skipfrom = Some(start);
+            b.seek(stop.max(from));
+            return;
+        }
+    };
+    r.warnings.push(Warning::SkippedBody { offset: start, end: b.filepos(end) });
+    b.seek(end);
+}
+
+/// First position in `range` where a few consecutive operations look valid
+pub(crate) fn next_plausible(data: &[u8], mut range: Range<usize>, profile: &VersionProfile) -> Option<usize> {
+    range.find(|&pos| plausible_at(data, pos, profile))
+}
+
+/// Whether `RESYNC_CHAIN` consecutive operations starting at `pos` look valid. Reaching the end of body after
+/// the first complete operation counts
+fn plausible_at(data: &[u8], mut pos: usize, profile: &VersionProfile) -> bool {
+    for i in 0..RESYNC_CHAIN {
+        if i > 0 && pos == data.len() {
+            return true;
+        }
+        match check_op(data, pos, profile) {
+            OpCheck::Valid(len) => pos += len,
+            OpCheck::Truncated => return i > 0,
+            OpCheck::Invalid => return false,
+        }
+    }
+    true
+}
+
+pub(crate) enum OpCheck {
+    /// Length of a valid looking operation
+    Valid(usize),
+    /// Looks valid so far but cut off by the end of body
+    Truncated,
+    Invalid,
+}
+
+/// Check the operation at `pos` by its type and length fields
+pub(crate) fn check_op(data: &[u8], pos: usize, profile: &VersionProfile) -> OpCheck {
+    match op_len(data, pos, profile) {
+        None => OpCheck::Truncated,
+        Some(None) => OpCheck::Invalid,
+        Some(Some(len)) if pos + len > data.len() => OpCheck::Truncated,
+        Some(Some(len)) => OpCheck::Valid(len),
+    }
+}
+
+/// Length of the operation at `pos`. `None` if its fields are cut off, `Some(None)` if it doesn't look valid
+fn op_len(data: &[u8], pos: usize, profile: &VersionProfile) -> Option<Option<usize>> {
+    let at = |offset: usize| peek_i32_at(data, pos + offset);
+    let valid_len = |len: i32| usize::try_from(len).ok().filter(|len| *len <= MAX_CMD_LEN);
+    Some(match at(0)? {
+        OP_COMMAND => valid_len(at(4)?).filter(|len| *len > 0).map(|len| 12 + len),
+        OP_SYNC if !(0..=1000).contains(&at(4)?) => None,
+        OP_SYNC if profile.sync_checksums => Some(if at(8) == Some(0) { 8 + 360 } else { 8 }),
+        OP_SYNC => Some(if at(8)? != 0x03 { 12 + 28 + 12 } else { 12 + 12 }),
+        // Achievements of DE take the rest of body
+        OP_POSTGAME if profile.postgame => Some(data.len() - pos),
+        OP_VIEWLOCK => {
+            let coord = |v: i32| {
+                let v = f32::from_bits(v as u32);
+                v == 0.0 || v.is_normal() && (0.0..=MAX_VIEW_COORD).contains(&v)
+            };
+            (coord(at(4)?) && coord(at(8)?) && (0..=8).contains(&at(12)?)).then_some(16)
+        }
+        OP_CHAT => match at(4)? {
+            500 => Some(8 + profile.chat500_len),
+            -1 => valid_len(at(8)?).map(|len| 12 + len),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+pub(crate) fn peek_i32_at(data: &[u8], pos: usize) -> Option<i32> {
+    data.get(pos..pos.checked_add(4)?).map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+}
+
+/// Chat message of a chat operation. Empty and system messages are skipped
+pub(crate) fn body_chat(msg: Vec<u8>, profile: &VersionProfile, time: u32) -> Option<Chat> {
+    if profile.json_chat {
+        return parse_de_chat(&msg, time);
+    }
+    if msg.len() >= 7 && msg.starts_with(b"@#") && msg.ends_with(b"--") && msg[3] == b'-' && msg[4] == b'-'
+        || msg.is_empty()
+    {
+        return None;
+    }
+    Some(Chat { time: Some(time), player: None, content_raw: Some(msg), content: None })
+}
+
+/// DE chat messages are JSON like `{"player":1,"message":"gg","messageAGP":"@#1name: gg",...}`.
+/// `messageAGP` has the same format as chats of older versions, but it's empty sometimes
+fn parse_de_chat(raw: &[u8], time: u32) -> Option<Chat> {
+    let json: serde_json::Value = serde_json::from_slice(raw).ok()?;
+    let content = match json["messageAGP"].as_str() {
+        Some(x) if !x.is_empty() => x,
+        _ => json["message"].as_str()?,
+    };
+    if content.is_empty() {
+        return None;
+    }
+    Some(Chat {
+        time: Some(time),
+        player: json["player"].as_u64().map(|x| x as u8),
+        content_raw: Some(content.as_bytes().to_vec()),
+        content: None,
+    })
 }
@@ -13,6 +13,8 @@ pub struct StreamCursor<T: AsRef<[u8]>> {
     pub src: T,
     pub pos_in_data: usize,
     pub offset: usize,
+    /// Position of the source stream in the file. Not zero for windows of a file read from `io::Read`
+    pub base: usize,
 }
 
 // Due to limitation of the boyer_moore_magiclen crate, we need to implement the BMByteSearchable trait for u8 slice.
@@ -46,11 +48,16 @@ impl<'a> BMByteSearchable for SearchableU8<'a> {
 impl<T: AsRef<[u8]>> StreamCursor<T> {
     /// Offset is the start position of the data stream
     pub fn new(src: T, offset: usize) -> Self {
-        StreamCursor { src, pos_in_data: 0, offset }
+        StreamCursor { src, pos_in_data: 0, offset, base: 0 }
+    }
+
+    /// Position in the file of a position in the data stream
+    pub fn filepos(&self, pos_in_data: usize) -> usize {
+        self.base + self.offset + pos_in_data
     }
 
     pub fn mov(&mut self, dist: isize) -> &mut Self {
-        let dest = self.pos_in_data as isize + dist;
+        let dest = (self.pos_in_data as isize).saturating_add(dist);
         if dest < 0 {
             self.pos_in_data = 0;
         } else if dest as usize > self.data().len() {
@@ -61,13 +68,13 @@ impl<T: AsRef<[u8]>> StreamCursor<T> {
         self
     }
 
-    /// Start from offset. Real data I need.
+    /// Start from offset. Real data I need. Empty if offset is out of the source stream
     pub fn data(&self) -> &[u8] {
-        &self.src.as_ref()[self.offset..]
+        self.src.as_ref().get(self.offset..).unwrap_or_default()
     }
 
     pub fn current(&self) -> &[u8] {
-        &self.src.as_ref()[self.pos_in_data + self.offset..]
+        self.data().get(self.pos_in_data..).unwrap_or_default()
     }
 
     pub fn seek(&mut self, pos_in_data: usize) -> &mut Self {
@@ -81,22 +88,22 @@ impl<T: AsRef<[u8]>> StreamCursor<T> {
     }
 
     pub fn remain(&self) -> usize {
-        self.src.as_ref().len() - self.pos_in_data - self.offset
+        self.current().len()
     }
 
-    /// `range` is the range in the actual data stream
+    /// `range` is the range in the actual data stream. Nothing is found if it's out of the data stream
     pub fn find(&self, needle: Vec<u8>, range: Range<usize>) -> Option<usize> {
         if let Some(bmb) = BMByte::from(&needle) {
-            let slice = SearchableU8::from(&self.src.as_ref()[range.start + self.offset..range.end + self.offset]);
+            let slice = SearchableU8::from(self.data().get(range.clone())?);
             return bmb.find_first_in(slice).map(|pos| pos + range.start);
         }
         None
     }
 
-    /// `range` is the range in the actual data stream
+    /// `range` is the range in the actual data stream. Nothing is found if it's out of the data stream
     pub fn rfind(&self, needle: &Vec<u8>, range: Range<usize>) -> Option<usize> {
         if let Some(bmb) = BMByte::from(needle) {
-            let slice = SearchableU8::from(&self.src.as_ref()[range.start + self.offset..range.end + self.offset]);
+            let slice = SearchableU8::from(self.data().get(range.clone())?);
             return bmb.rfind_first_in(slice).map(|pos| pos + range.start);
         }
         None
@@ -217,6 +224,31 @@ impl<T: AsRef<[u8]>> StreamCursor<T> {
         result
     }
 
+    pub fn peek_u64(&self) -> Option<u64> {
+        if self.remain() < 8 {
+            None
+        } else {
+            let raw_bytes = self.current()[..8].try_into();
+            match raw_bytes {
+                Ok(bytes) => {
+                    let result = u64::from_le_bytes(bytes);
+                    Some(result)
+                }
+                Err(_) => {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn get_u64(&mut self) -> Option<u64> {
+        let result = self.peek_u64();
+        if result.is_some() {
+            self.pos_in_data += 8;
+        }
+        result
+    }
+
     pub fn peek_i32(&self) -> Option<i32> {
         if self.remain() < 4 {
             None
@@ -261,13 +293,7 @@ impl<T: AsRef<[u8]>> StreamCursor<T> {
 
     pub fn get_bool(&mut self, bytes: u8) -> Option<bool> {
         // if next byets bytes are all 0, return false, otherwise true
-        let mut result = false;
-        for i in 0..bytes {
-            if self.current()[i as usize] != 0 {
-                result = true;
-                break;
-            }
-        }
+        let result = self.current().get(..bytes as usize)?.iter().any(|x| *x != 0);
         self.pos_in_data += bytes as usize;
         Some(result)
     }
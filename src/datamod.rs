@@ -0,0 +1,43 @@
+use crate::translations::{en, zh};
+use crate::{Record, Version};
+use phf::phf_map;
+
+/// Names of data mods of UserPatch 1.5 or higher. Mods not listed here still get their id and version
+static MODS: phf::Map<u32, &'static str> = phf_map! {
+    1u32 => "WololoKingdoms",
+};
+
+impl Record {
+    /// Data mod is stored in player resource 198 as a float of decimal digits: `1581.0` is mod `1` of version
+    /// `5.8.1`
+    pub(crate) fn detect_datamod(&mut self, raw: f32) {
+        if !(1.0..=u32::MAX as f32).contains(&raw) || self.modid.is_some() {
+            return;
+        }
+        let raw = raw as u32;
+        let id = raw / 1000;
+        let digits = format!("{:03}", raw % 1000);
+        self.modid = Some(id);
+        self.modname = MODS.get(&id).map(|x| x.to_string());
+        self.modversion = Some(digits.chars().map(String::from).collect::<Vec<_>>().join("."));
+    }
+
+    /// Mods with a version string of their own. The Forgotten Empires 2.1 writes `VER 9.5`, its civs share ids with
+    /// HD/DE
+    pub(crate) fn detect_mod_by_version(&mut self) {
+        if self.ver == Some(Version::AoFE21) {
+            self.modname = Some("The Forgotten Empires".to_string());
+            self.modversion = Some("2.1".to_string());
+        }
+    }
+
+    /// Name of unit `id` in `lang`, "en" or "zh". Ids are those of HD/DE, which WololoKingdoms follows. Other data
+    /// mods may have other ids, they are not known
+    pub fn unit_name(&self, id: i32, lang: &str) -> Option<&'static str> {
+        let units = match lang {
+            "en" => &en::UNITS_TRANS,
+            _ => &zh::UNITS_TRANS,
+        };
+        units.get(&id).copied()
+    }
+}
@@ -0,0 +1,149 @@
+use crate::cursor::StreamCursor;
+use crate::error::{Error, Result, Section, Warning};
+use crate::layout::walk_de_map;
+use crate::record::*;
+use crate::val;
+use crate::Parser;
+use std::collections::BTreeMap;
+
+const SEPARATOR: [u8; 4] = [0xa3, 0x5f, 0x02, 0x00];
+
+/// DE strings are prefixed with `0x60 0x0a` and a u16 length. Empty strings are `None`
+fn extract_str_de(h: &mut StreamCursor<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    if h.current().get(..2) != Some(&[0x60, 0x0a]) {
+        return Err(Error::OutOfBounds { section: Section::Unknown, offset: 0, reason: "Bad DE string".into() });
+    }
+    h.mov(2);
+    let str_len = val!(h.get_u16()) as usize;
+    if str_len > h.remain() {
+        return Err(Error::OutOfBounds {
+            section: Section::Unknown,
+            offset: 0,
+            reason: "DE string is too long".into(),
+        });
+    }
+    let raw_str = h.current()[..str_len].to_vec();
+    h.mov(str_len as isize);
+    Ok(if raw_str.is_empty() { None } else { Some(raw_str) })
+}
+
+pub(crate) fn skip_separator(h: &mut StreamCursor<Vec<u8>>) -> Result<()> {
+    if h.current().get(..4) != Some(&SEPARATOR) {
+        return Err(Error::SectionNotFound { section: Section::Unknown, offset: 0 });
+    }
+    h.mov(4);
+    Ok(())
+}
+
+impl<T: AsRef<[u8]>> Parser<T> {
+    /// Parse header of DE records of save version 63.x. Header cursor should be right after version info.
+    /// https://github.com/happyleavesaoc/aoc-mgz/blob/master/mgz/header/de.py
+    pub(crate) fn parse_de_header(&mut self, r: &mut Record) -> Result<()> {
+        r.debug.section = Section::Settings;
+        let h = &mut self.header;
+
+        h.mov(4 + 4); // build, timestamp
+        h.mov(4 + 4 + 4); // version, interval version, game options version
+        let num_dlcs = val!(h.get_u32());
+        h.mov(num_dlcs as isize * 4);
+        h.mov(4); // dataset
+        r.difficulty_raw = h.get_i32();
+        h.mov(4); // selected map
+        r.mapid = h.get_u32();
+        r.revealmap_raw = h.get_i32();
+        r.victorytype_raw = h.get_i32();
+        r.resources_raw = h.get_i32();
+        // Ages of DE start from 2(Dark Age)
+        r.startingage_raw = h.get_i32().filter(|x| *x >= 2).map(|x| x - 2);
+        h.mov(4); // ending age
+        r.gametype_raw = h.get_u32().map(|x| x as u8);
+        skip_separator(h)?;
+        skip_separator(h)?;
+
+        r.speed_raw = h.get_f32().map(|x| (x * 100.0).round() as u32);
+        h.mov(4); // treaty length
+        r.poplimit = h.get_u32();
+        r.totalplayers = h.get_u32().map(|x| (x as u8).saturating_add(1));
+        h.mov(4 + 4 + 1); // unused player color, victory amount, unknown
+        skip_separator(h)?;
+
+        h.mov(2); // trade enabled, team bonus disabled
+        r.randompositions = h.get_bool(1);
+        // All techs is the full tech tree option of DE lobby
+        r.alltechs = h.get_bool(1);
+        r.fulltechtree = r.alltechs;
+        h.mov(1); // starting units
+        r.lockteams = h.get_bool(1);
+        r.lockspeed = h.get_bool(1);
+        r.ismultiplayer = h.get_bool(1);
+        r.enablecheats = h.get_bool(1);
+        h.mov(1 + 1 + 1 + 1 + 1); // record game, animals, predators, turbo, shared exploration
+        r.teamtogether = h.get_bool(1);
+        h.mov(4 + 4 + 1 + 1); // sub game mode, battle royale time, handicap, unknown
+        skip_separator(h)?;
+
+        r.debug.section = Section::Lobby;
+        r.players[0].index = Some(0);
+        for i in 1..9 {
+            h.mov(4); // dlc id
+            let colorid = val!(h.get_i32());
+            h.mov(1 + 1); // selected color, selected team
+            r.players[i].teamid = h.get_u8();
+            h.mov(8 + 1); // dat crc, mp game version
+            r.players[i].civ_raw = h.get_u32().map(|x| x as u8);
+            let num_custom_civs = val!(h.get_u32());
+            h.mov(num_custom_civs as isize * 4);
+            extract_str_de(h)?; // ai type
+            h.mov(1); // ai civ name index
+            let ainame = extract_str_de(h)?;
+            r.players[i].name_raw = extract_str_de(h)?.or(ainame);
+            r.players[i].playertype = h.get_i32();
+            r.players[i].profileid = h.get_u32().filter(|x| *x != u32::MAX);
+            h.mov(4);
+            r.players[i].index = h.get_i32();
+            h.mov(1 + 1 + 8); // prefer random, custom ai, handicap
+            if colorid >= 0 {
+                r.players[i].colorid = Some(colorid as u8);
+            }
+            if r.players[i].isvalid() {
+                // Co-op partners share the player index of the first slot
+                let index = r.players[i].index;
+                r.players[i].ismainop = Some(!r.players[1..i].iter().any(|p| p.isvalid() && p.index == index));
+            }
+        }
+
+        h.mov(9);
+        r.fogofwar = h.get_bool(1);
+        h.mov(1 + 1); // cheat notifications, colored chat
+        skip_separator(h)?;
+        h.mov(1); // ranked
+        r.allowspectators = h.get_bool(1);
+
+        r.debug.section = Section::Map;
+        match walk_de_map(h) {
+            Some(map) => {
+                r.mapx = Some(map.size);
+                r.mapy = Some(map.size);
+                r.debug.mappos = Some(map.tiles);
+                r.modname = map.dataset.map(|x| String::from_utf8_lossy(&x).into_owned());
+            }
+            None => r.warnings.push(Warning::MapNotFound),
+        }
+
+        // Team 1 means no team
+        let mut teams: BTreeMap<u8, Vec<i32>> = BTreeMap::new();
+        for p in r.players.iter().filter(|p| p.isvalid() && p.ismainop == Some(true)) {
+            let idx = val!(p.index);
+            match p.teamid {
+                Some(teamid) if teamid > 1 => teams.entry(teamid).or_default().push(idx),
+                _ => r.teams.push(vec![idx]),
+            }
+        }
+        r.teams.extend(teams.into_values());
+        let mut team_count: Vec<usize> = r.teams.iter().map(|t| t.len()).collect();
+        team_count.sort();
+        r.matchup = Some(team_count);
+
+        Ok(())
+    }
+}
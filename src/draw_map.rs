@@ -1,52 +1,78 @@
+use crate::error::{Error, Result, Section};
+use crate::layout::DE_TILE_LEN;
 use crate::mapcolors::{MAP_COLORS, PLAYER_COLORS};
 use crate::Parser;
 use crate::Record;
-use anyhow::{bail, Result};
-use image::Rgb;
+use image::{Rgb, RgbImage};
 use imageproc::drawing::draw_filled_circle_mut;
 
+/// Map image error at map tiles `offset`
+fn image_error(offset: usize, reason: impl ToString) -> Error {
+    Error::Image { section: Section::Map, offset, reason: reason.to_string() }
+}
+
 /// Generate minimap from map data. Save it to `savename.png` 
 pub fn draw_map<T: AsRef<[u8]>>(rec: &Record, parser: &Parser<T>, savename: &str) -> Result<()> {
+    map_image(rec, parser)?.save(savename).map_err(|e| image_error(rec.debug.mappos.unwrap_or_default(), e))
+}
+
+/// Generate minimap from map data without saving it
+pub fn map_image<T: AsRef<[u8]>>(rec: &Record, parser: &Parser<T>) -> Result<RgbImage> {
     let offset = match rec.debug.mappos {
         Some(pos) => pos,
-        None => bail!("No offset"),
+        None => return Err(image_error(0, "No offset")),
     };
 
     let src_width = match rec.mapx {
-        Some(x) => x as u32,
-        None => bail!("No mapx"),
+        Some(x) if (0..=10000).contains(&x) => x as u32,
+        Some(_) => return Err(image_error(offset, "Bad mapx")),
+        None => return Err(image_error(offset, "No mapx")),
     };
 
     let src_height = match rec.mapy {
-        Some(y) => y as u32,
-        None => bail!("No mapy"),
+        Some(y) if (0..=10000).contains(&y) => y as u32,
+        Some(_) => return Err(image_error(offset, "Bad mapy")),
+        None => return Err(image_error(offset, "No mapy")),
     };
 
-    let rawdata = &parser.header.data()[offset as usize..];
+    let rawdata = match parser.header.data().get(offset..) {
+        Some(data) if !data.is_empty() => data,
+        _ => return Err(image_error(offset, "Map data is out of header")),
+    };
 
-    let mut img = image::RgbImage::new(src_width, src_height);
+    let is_legacy = rawdata[0] != 0xff;
+    let (terrain_offset, elevation_offset, struct_len) = if rec.profile().de_tiles {
+        (0, 3, DE_TILE_LEN as u32)
+    } else if is_legacy {
+        (0, 1, 2)
+    } else {
+        (1, 2, 4)
+    };
+    if rawdata.len() < (src_width * src_height * struct_len) as usize {
+        return Err(image_error(offset, "Map data is incomplete"));
+    }
 
-    let is_legacy = *rawdata.first().unwrap() != 0xff;
-    let (terrain_offset, elevation_offset, struct_len) = if is_legacy { (0, 1, 2) } else { (1, 2, 4) };
+    let mut img = RgbImage::new(src_width, src_height);
 
     for (x, y, pixel) in img.enumerate_pixels_mut() {
-        let cur_offset = (y * src_width + x) * struct_len;
-        let rightbottom_offset = ((y + 1) * src_width + x + 1) * struct_len;
+        let cur_offset = ((y * src_width + x) * struct_len) as usize;
+        let rightbottom_offset = (((y + 1) * src_width + x + 1) * struct_len) as usize;
         let mut elevation = 1;
 
-        if x < src_width - 1 && y < src_height - 1 {
-            if *rawdata.get((cur_offset + elevation_offset) as usize).unwrap()
-                > *rawdata.get((rightbottom_offset + elevation_offset) as usize).unwrap()
-            {
+        if x + 1 < src_width && y + 1 < src_height {
+            let cur_elevation = rawdata[cur_offset + elevation_offset];
+            let rightbottom_elevation = rawdata[rightbottom_offset + elevation_offset];
+            if cur_elevation > rightbottom_elevation {
                 elevation = 0;
-            } else if *rawdata.get((cur_offset + elevation_offset) as usize).unwrap()
-                < *rawdata.get((rightbottom_offset + elevation_offset) as usize).unwrap()
-            {
+            } else if cur_elevation < rightbottom_elevation {
                 elevation = 2;
             }
         }
 
-        *pixel = Rgb(MAP_COLORS[*rawdata.get((cur_offset + terrain_offset) as usize).unwrap() as usize][elevation]);
+        // Terrains unknown to the color table are left black
+        if let Some(colors) = MAP_COLORS.get(rawdata[cur_offset + terrain_offset] as usize) {
+            *pixel = Rgb(colors[elevation]);
+        }
     }
 
     for player in &rec.players {
@@ -54,8 +80,13 @@ pub fn draw_map<T: AsRef<[u8]>>(rec: &Record, parser: &Parser<T>, savename: &str
             continue;
         }
 
-        if let (Some(x), Some(y), Some(color_id)) = (player.initx, player.inity, player.colorid) {
-            let color = PLAYER_COLORS[color_id as usize];
+        if let (Some(x), Some(y), Some(color)) =
+            (player.initx, player.inity, player.colorid.and_then(|id| PLAYER_COLORS.get(id as usize)))
+        {
+            // Positions out of the map are garbage
+            if !(0.0..src_width as f32).contains(&x) || !(0.0..src_height as f32).contains(&y) {
+                continue;
+            }
             let rgb = image::Rgb([color[0] as u8, color[1] as u8, color[2] as u8]);
 
             // Draw players as dots
@@ -64,6 +95,5 @@ pub fn draw_map<T: AsRef<[u8]>>(rec: &Record, parser: &Parser<T>, savename: &str
         }
     }
 
-    img.save(savename)?;
-    Ok(())
+    Ok(img)
 }
@@ -0,0 +1,185 @@
+use serde::Serialize;
+use std::fmt;
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// Section of the recorded game where an error occurs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
+pub enum Section {
+    /// The file itself, before the header is decompressed
+    File,
+    /// Version info at the beginning of header
+    Version,
+    Ai,
+    Replay,
+    Map,
+    Init,
+    Trigger,
+    Lobby,
+    Settings,
+    Victory,
+    Scenario,
+    Body,
+    /// Not located yet. Errors returned by `Parser` always have a section
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Error of parsing a recorded game. `offset` is the position in the decompressed header for header sections,
+/// or the position in the file for `Section::File` and `Section::Body`
+#[derive(Debug)]
+pub enum Error {
+    /// Version is detected but not supported by the parser
+    UnsupportedVersion { section: Section, offset: usize, reason: &'static str },
+    /// Header or a chapter header can't be decompressed
+    Decompression { section: Section, offset: usize, reason: String },
+    /// A section located by searching for its signature is not found
+    SectionNotFound { section: Section, offset: usize },
+    /// Data ends before an expected value, or a value is out of its valid range
+    OutOfBounds { section: Section, offset: usize, reason: String },
+    /// Unknown operation or malformed operation in body
+    BadBodyOp { section: Section, offset: usize, op: i32 },
+    /// A size or count exceeds its limit in `ParseOptions.limits`
+    LimitExceeded { section: Section, offset: usize, limit: &'static str, max: usize },
+    /// Failed to read the file
+    Io { section: Section, offset: usize, source: std::io::Error },
+    /// Parsing panicked in `parse_many()`, a bug of the parser. `section` is `Unknown`
+    Panicked { section: Section, offset: usize, message: String },
+    /// Map image can't be generated from map data, or can't be saved. `section` is `Map`, `offset` is the position
+    /// of map tiles if known
+    Image { section: Section, offset: usize, reason: String },
+    /// Record can't be serialized to JSON. `section` is `Unknown`
+    Serialize { section: Section, offset: usize, reason: String },
+}
+
+impl Error {
+    pub fn section(&self) -> Section {
+        match self {
+            Error::UnsupportedVersion { section, .. }
+            | Error::Decompression { section, .. }
+            | Error::SectionNotFound { section, .. }
+            | Error::OutOfBounds { section, .. }
+            | Error::BadBodyOp { section, .. }
+            | Error::LimitExceeded { section, .. }
+            | Error::Io { section, .. }
+            | Error::Panicked { section, .. }
+            | Error::Image { section, .. }
+            | Error::Serialize { section, .. } => *section,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        match self {
+            Error::UnsupportedVersion { offset, .. }
+            | Error::Decompression { offset, .. }
+            | Error::SectionNotFound { offset, .. }
+            | Error::OutOfBounds { offset, .. }
+            | Error::BadBodyOp { offset, .. }
+            | Error::LimitExceeded { offset, .. }
+            | Error::Io { offset, .. }
+            | Error::Panicked { offset, .. }
+            | Error::Image { offset, .. }
+            | Error::Serialize { offset, .. } => *offset,
+        }
+    }
+
+    /// Whether the error can be caused by data ending early. Only these are tolerated in truncated files
+    pub(crate) fn is_cut(&self) -> bool {
+        matches!(self, Error::OutOfBounds { .. } | Error::SectionNotFound { .. })
+    }
+
+    /// Set section and offset of errors not located yet, e.g. errors from `val!`
+    pub(crate) fn locate(mut self, at: Section, pos: usize) -> Self {
+        match &mut self {
+            Error::UnsupportedVersion { section, offset, .. }
+            | Error::Decompression { section, offset, .. }
+            | Error::SectionNotFound { section, offset }
+            | Error::OutOfBounds { section, offset, .. }
+            | Error::BadBodyOp { section, offset, .. }
+            | Error::LimitExceeded { section, offset, .. }
+            | Error::Io { section, offset, .. }
+            | Error::Panicked { section, offset, .. }
+            | Error::Image { section, offset, .. }
+            | Error::Serialize { section, offset, .. } => {
+                if *section == Section::Unknown {
+                    *section = at;
+                    *offset = pos;
+                }
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedVersion { reason, .. } => write!(f, "Unsupported version: {}", reason)?,
+            Error::Decompression { reason, .. } => write!(f, "Corrupt compression: {}", reason)?,
+            Error::SectionNotFound { section, .. } => write!(f, "Section not found: {}", section)?,
+            Error::OutOfBounds { reason, .. } => write!(f, "Out of bounds: {}", reason)?,
+            Error::BadBodyOp { op, .. } => write!(f, "Bad body operation: {}", op)?,
+            Error::LimitExceeded { limit, max, .. } => write!(f, "Limit exceeded: {} > {}", limit, max)?,
+            Error::Io { source, .. } => write!(f, "I/O error: {}", source)?,
+            Error::Panicked { message, .. } => write!(f, "Parser panicked: {}", message)?,
+            Error::Image { reason, .. } => write!(f, "Map image failed: {}", reason)?,
+            Error::Serialize { reason, .. } => write!(f, "Serialization failed: {}", reason)?,
+        }
+        write!(f, " @ {}:{}", self.section(), self.offset())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Non-fatal problem found during parsing. The record is still usable but may be incomplete.
+/// Offsets in body are positions in the file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Warning {
+    /// Init data of the player is not found, its initial resources, civ, color and position are missing
+    PlayerInitNotFound { slot: usize },
+    /// Operations of types unknown to the parser from `offset` to `end`, skipped 4 bytes each. A run of them is
+    /// one warning, `op` is the type of the first one. Reported as `SkippedBody` when `ParseOptions.recover` is on
+    UnknownBodyOp { offset: usize, end: usize, op: i32 },
+    /// Time delta of a sync operation is out of `0..=1000`, ignored. Reported as `SkippedBody` when
+    /// `ParseOptions.recover` is on
+    UnusualSyncDelta { offset: usize, delta: i32 },
+    /// Garbage in body from `offset` to `end` is skipped, parsing resumes at the next plausible operation.
+    /// `end` is the end of body if nothing plausible follows
+    SkippedBody { offset: usize, end: usize },
+    /// Command is longer than the rest of body. Commands with a bad length are skipped as `SkippedBody` when
+    /// `ParseOptions.recover` is on
+    TruncatedCommand { offset: usize, cmd: u8, len: usize },
+    /// Encoding of in-game strings is not detected, a fallback encoding is used
+    EncodingFallback { encoding: &'static str },
+    /// Walking header from player init data to triggers failed, the sections are located by searching for magic
+    /// values and player init data by player names instead, they may be wrong
+    HeaderSearched,
+    /// Scenario is not at its usual distance after player init data, it's located by searching for its version.
+    /// Scenarios may have more data there
+    ScenarioSearched,
+    /// Map of DE is not found by its structure, map size and map image are missing
+    MapNotFound,
+    /// A lobby setting in instructions differs from the one read from header, the header value is kept
+    InstructionsMismatch { setting: &'static str, instructions: String },
+    /// A lobby setting in instructions has a value unknown to the parser, it's not used
+    UnknownInstructionValue { setting: &'static str, value: String },
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::Io { section: Section::File, offset: 0, source }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::body_parser::{
+    body_chat, check_op, next_plausible, peek_i32_at, OpCheck, OP_CHAT, OP_COMMAND, OP_POSTGAME, OP_SYNC, OP_VIEWLOCK,
+};
+use crate::error::{Error, Result, Section};
+use crate::parser::{peek_u32_at, ParseOptions};
+use crate::profile::VersionProfile;
+use crate::record::Chat;
+
+/// Kind of a command in body, decoded from its first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandKind {
+    Order,
+    Move,
+    Resign,
+    Save,
+    Chapter,
+    TrainSingle,
+    Research,
+    Build,
+    Tribute,
+    Train,
+    Postgame,
+    Other(u8),
+}
+
+impl From<u8> for CommandKind {
+    fn from(id: u8) -> Self {
+        match id {
+            0x00 => CommandKind::Order,
+            0x03 => CommandKind::Move,
+            0x0b => CommandKind::Resign,
+            0x1b => CommandKind::Save,
+            0x20 => CommandKind::Chapter,
+            0x64 => CommandKind::TrainSingle,
+            0x65 => CommandKind::Research,
+            0x66 => CommandKind::Build,
+            0x6c => CommandKind::Tribute,
+            0x77 => CommandKind::Train,
+            0xff => CommandKind::Postgame,
+            x => CommandKind::Other(x),
+        }
+    }
+}
+
+/// An operation in body. `time` is the game time in milliseconds when it happens, time before the restore point is
+/// included for restored games
+#[derive(Debug)]
+pub enum BodyEvent<'a> {
+    /// Game time goes on by `delta`, `time` includes it
+    Sync { time: u32, delta: u32 },
+    /// `bytes` is the command data starting with the command id
+    Command { time: u32, kind: CommandKind, bytes: &'a [u8] },
+    /// In-game chat, same as those in `Record.chat`
+    Chat(Chat),
+    /// The recorder's view moves to `x`, `y`
+    ViewLock { time: u32, x: f32, y: f32, player: i32 },
+}
+
+/// Iterator of operations in body, see `Parser::events()`. It stops after an error
+pub struct Events<'a> {
+    /// The whole file, positions are file positions
+    data: &'a [u8],
+    profile: &'static VersionProfile,
+    options: &'a ParseOptions,
+    pos: usize,
+    /// End of the current chapter
+    end: usize,
+    /// No position of the next chapter
+    single: bool,
+    time: u32,
+    opcount: usize,
+    done: bool,
+}
+
+impl<'a> Events<'a> {
+    /// Operations start at `pos` after body meta, `nextpos` is the position of the next chapter in the file
+    pub(crate) fn new(
+        data: &'a [u8],
+        profile: &'static VersionProfile,
+        options: &'a ParseOptions,
+        pos: usize,
+        nextpos: u32,
+        time: u32,
+    ) -> Self {
+        let single = !profile.chapters || nextpos == 0;
+        let end = if single { data.len() } else { (nextpos as usize).min(data.len()) };
+        Events { data, profile, options, pos, end, single, time, opcount: 0, done: pos >= data.len() }
+    }
+
+    /// Move to the body of the next chapter. `false` if there is none
+    fn next_chapter(&mut self) -> Result<bool> {
+        if self.single || self.end >= self.data.len() {
+            return Ok(false);
+        }
+        let headerpos = self.end;
+        let (Some(start), Some(nextpos)) = (peek_u32_at(self.data, headerpos), peek_u32_at(self.data, headerpos + 4))
+        else {
+            return Ok(false);
+        };
+        if nextpos == 0 {
+            self.end = self.data.len();
+        } else if nextpos as usize > self.end {
+            self.end = (nextpos as usize).min(self.data.len());
+        } else {
+            // Chapters never go backwards, or the iterator would never end
+            return Err(Error::OutOfBounds {
+                section: Section::Body,
+                offset: headerpos + 4,
+                reason: format!("Next chapter @ {} is before current chapter", nextpos),
+            });
+        }
+        self.pos = start as usize;
+        self.check_first_op()?;
+        Ok(true)
+    }
+
+    /// Body of each chapter starts with a sync operation
+    pub(crate) fn check_first_op(&self) -> Result<()> {
+        match peek_i32_at(self.data, self.pos) {
+            Some(op) if self.options.strict && op != OP_SYNC => {
+                Err(Error::BadBodyOp { section: Section::Body, offset: self.pos, op })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<BodyEvent<'a>>> {
+        loop {
+            if self.pos >= self.end && !self.next_chapter()? {
+                return Ok(None);
+            }
+            let data = &self.data[..self.end];
+            let pos = self.pos;
+            let len = match check_op(data, pos, self.profile) {
+                OpCheck::Valid(len) => len,
+                // An operation cut off by the end of the chapter, the next chapter goes on as in `parse_body()`
+                OpCheck::Truncated => {
+                    self.pos = self.end;
+                    continue;
+                }
+                OpCheck::Invalid if self.options.recover => {
+                    self.pos = next_plausible(data, pos + 1..data.len(), self.profile).unwrap_or(self.end);
+                    continue;
+                }
+                OpCheck::Invalid => {
+                    let op = peek_i32_at(data, pos).unwrap_or_default();
+                    return Err(Error::BadBodyOp { section: Section::Body, offset: pos, op });
+                }
+            };
+            self.opcount += 1;
+            if self.opcount > self.options.limits.body_ops {
+                return Err(Error::LimitExceeded {
+                    section: Section::Body,
+                    offset: pos,
+                    limit: "body operations",
+                    max: self.options.limits.body_ops,
+                });
+            }
+            self.pos += len;
+
+            let op = &data[pos..pos + len];
+            let at = |offset: usize| peek_i32_at(op, offset).unwrap_or_default();
+            match at(0) {
+                OP_SYNC => {
+                    let delta = at(4) as u32;
+                    self.time = self.time.saturating_add(delta);
+                    return Ok(Some(BodyEvent::Sync { time: self.time, delta }));
+                }
+                OP_COMMAND => {
+                    let bytes = &op[8..8 + at(4) as usize];
+                    return Ok(Some(BodyEvent::Command { time: self.time, kind: bytes[0].into(), bytes }));
+                }
+                OP_VIEWLOCK => {
+                    let (x, y) = (f32::from_bits(at(4) as u32), f32::from_bits(at(8) as u32));
+                    return Ok(Some(BodyEvent::ViewLock { time: self.time, x, y, player: at(12) }));
+                }
+                OP_CHAT if at(4) == -1 => {
+                    let mut msg = &op[12..];
+                    if let Some((0, rest)) = msg.split_last() {
+                        msg = rest;
+                    }
+                    if let Some(chat) = body_chat(msg.to_vec(), self.profile, self.time) {
+                        return Ok(Some(BodyEvent::Chat(chat)));
+                    }
+                }
+                // Achievements of DE take the rest of body
+                OP_POSTGAME => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<BodyEvent<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_event().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
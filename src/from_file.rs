@@ -1,29 +1,53 @@
-use crate::{Parser, Record};
-use anyhow::{Result, anyhow};
+use crate::error::Result;
+use crate::{ParseOptions, Parser, Record};
+use memmap2::Mmap;
 use std::fs::{self, File};
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::Path;
 use std::time::UNIX_EPOCH;
 
 /// Parse a recorded game file into a `Record` and `Parser`. Game info can be accessed from `Record`
 pub fn from_file(file: &str) -> Result<(Record, Parser<Vec<u8>>)> {
-    let path = Path::new(file);
+    from_path(Path::new(file), ParseOptions::default())
+}
+
+pub(crate) fn from_path(path: &Path, options: ParseOptions) -> Result<(Record, Parser<Vec<u8>>)> {
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
 
-    // Get file metadata
+    let mut record = new_record(path, buffer.len())?;
+    let mut parser = Parser::<Vec<u8>>::with_options(buffer, options)?;
+    parser.parse_to(&mut record)?;
+
+    Ok((record, parser))
+}
+
+/// Same as `from_file()`, but the file is memory-mapped instead of read into a buffer. Body is parsed from the
+/// mapped bytes directly, only the header is decompressed into memory.
+/// The file must not be modified or truncated by other processes while the `Parser` is alive
+pub fn from_file_mmap(file: &str) -> Result<(Record, Parser<Mmap>)> {
+    let path = Path::new(file);
+    let file = File::open(path)?;
+    // Safety: the map is read only. Changes to the file by others are not guarded against, see above
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    let mut record = new_record(path, mmap.len())?;
+    let mut parser = Parser::new(mmap)?;
+    parser.parse_to(&mut record)?;
+
+    Ok((record, parser))
+}
+
+/// Record with file name and last modified time from file metadata
+fn new_record(path: &Path, filesize: usize) -> Result<Record> {
     let metadata = fs::metadata(path)?;
     let filename = path.file_name()
-        .ok_or_else(|| anyhow!("Failed to get file name"))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Failed to get file name"))?
         .to_str()
-        .ok_or_else(|| anyhow!("Failed to convert file name to &str"))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Failed to convert file name to &str"))?
         .to_string();
-    let last_modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_millis();
-
-    let mut record = Record::new(filename, buffer.len(), last_modified);
-    let mut parser = Parser::<Vec<u8>>::new(buffer).unwrap();
-    parser.parse_to(&mut record)?;
+    let last_modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_err(io::Error::other)?.as_millis();
 
-    Ok((record, parser))
+    Ok(Record::new(filename, filesize, last_modified))
 }
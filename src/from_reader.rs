@@ -0,0 +1,247 @@
+use crate::body_parser::{check_first_op, parse_window, WindowEnd, WINDOW_MARGIN};
+use crate::cursor::StreamCursor;
+use crate::error::{Error, Result, Section};
+use crate::guid::calc_guid;
+use crate::parser::{body_start, chapter_end, finish, inflate_header, parse_body_meta};
+use crate::record::{Chapter, Record};
+use crate::val;
+use crate::{ParseOptions, Parser};
+use chksum_hash_md5 as md5;
+use std::io::Read;
+
+/// Bytes of body parsed in a window at a time
+const CHUNK: usize = 1 << 20;
+
+/// A file read from `io::Read`. Only a window of it is kept in memory
+struct Source<R: Read> {
+    reader: R,
+    /// Kept bytes, starting at `pos` of the file
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    hasher: md5::Update,
+}
+
+impl<R: Read> Source<R> {
+    /// Position in the file after the kept bytes
+    fn end(&self) -> usize {
+        self.pos + self.buf.len()
+    }
+
+    /// Read until bytes before file position `until` are kept, or the file ends
+    fn fill(&mut self, until: usize) -> Result<()> {
+        if self.eof || self.end() >= until {
+            return Ok(());
+        }
+        let old = self.buf.len();
+        let wanted = (until - self.end()) as u64;
+        (&mut self.reader).take(wanted).read_to_end(&mut self.buf)?;
+        self.hasher.update(&self.buf[old..]);
+        self.eof = self.end() < until;
+        Ok(())
+    }
+
+    /// Drop bytes before file position `to`. Bytes not read yet are read and dropped
+    fn consume(&mut self, to: usize) -> Result<()> {
+        while self.end() < to && !self.eof {
+            self.pos = self.end();
+            self.buf.clear();
+            self.fill(to.min(self.pos + CHUNK))?;
+        }
+        let n = to.saturating_sub(self.pos).min(self.buf.len());
+        self.buf.drain(..n);
+        self.pos += n;
+        Ok(())
+    }
+
+    /// Inflate a header starting at file position `begin`, reading more of the file until the compressed stream ends.
+    /// Deflate barely grows incompressible data, so at most `compressed_max(limit)` bytes are read
+    fn inflate(&mut self, begin: usize, limit: usize) -> Result<(Vec<u8>, usize, bool)> {
+        let max = compressed_max(limit);
+        loop {
+            let (header, compressed_size, complete) = inflate_header(val!(self.buf.get(begin - self.pos..)), limit)?;
+            if complete || self.eof {
+                return Ok((header, compressed_size, complete));
+            }
+            // Inflating to almost nothing, e.g. empty blocks
+            let read = self.end() - begin;
+            if read >= max {
+                return Err(Error::LimitExceeded {
+                    section: Section::Unknown,
+                    offset: 0,
+                    limit: "header size",
+                    max: limit,
+                });
+            }
+            // Inflate again with twice the data, the total work is still linear
+            self.fill(begin + read.saturating_add(read.max(CHUNK)).min(max))?;
+        }
+    }
+}
+
+/// Most compressed bytes read for a header of at most `limit` bytes
+fn compressed_max(limit: usize) -> usize {
+    limit.saturating_add(CHUNK)
+}
+
+/// Parse a recorded game from any `io::Read`, e.g. a file in an archive.
+/// The header is kept in memory, body is parsed in windows of about 1 MiB, so memory doesn't grow with the length of
+/// the game. `r` is filled the same as `Parser::parse_to()` fills it, `r.filesize` is set to the bytes read.
+/// The returned `Parser` has the header only, `body` is empty. Skipped garbage may be reported differently when
+/// `ParseOptions.recover` is off
+pub fn from_reader<R: Read>(reader: R, r: &mut Record, options: ParseOptions) -> Result<Parser<Vec<u8>>> {
+    let mut src = Source { reader, buf: Vec::new(), pos: 0, eof: false, hasher: md5::default() };
+    src.fill(8)?;
+    if src.buf.len() < 8 {
+        return Err(Error::OutOfBounds { section: Section::File, offset: 0, reason: "File is too small".into() });
+    }
+    let b = &src.buf;
+    let rawheader_end = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    let nextpos = u32::from_le_bytes([b[4], b[5], b[6], b[7]]);
+
+    // Without the file length, try the header after the position of the next chapter first. AoK has no such position
+    // and the compressed header starts at 4, a wrong start fails in the first few bytes
+    src.fill(rawheader_end.min(8 + compressed_max(options.limits.header)).max(CHUNK))?;
+    let (header, compressed_size, complete, rawheader_begin) = match src.inflate(8, options.limits.header) {
+        Ok((header, compressed_size, complete)) => (header, compressed_size, complete, 8),
+        Err(Error::Decompression { .. }) => {
+            let (header, compressed_size, complete) =
+                src.inflate(4, options.limits.header).map_err(|e| e.locate(Section::File, 4))?;
+            (header, compressed_size, complete, 4)
+        }
+        Err(e) => return Err(e.locate(Section::File, 8)),
+    };
+    let body_start = body_start(rawheader_end, rawheader_begin, compressed_size);
+    src.consume(body_start)?;
+    src.fill(body_start + 4)?;
+
+    // Version detection needs the first u32 of body
+    let mut parser = Parser {
+        header: StreamCursor::new(header, 0),
+        body: StreamCursor::new(src.buf[..src.buf.len().min(4)].to_vec(), 0),
+        md5: String::new(),
+        options,
+        truncated: !complete,
+    };
+    r.debug.section = Section::Version;
+    r.truncated = parser.truncated;
+    let parsed = match parser.parse_header(r) {
+        Ok(()) if parser.options.header_only => Ok(()),
+        Ok(()) => parse_body_stream(&mut src, r, &parser.options, nextpos, parser.truncated)
+            .map_err(|e| e.locate(Section::Body, src.pos)),
+        // Keep what is parsed from a truncated header, sections after the cut are missing
+        Err(e) if parser.truncated && e.is_cut() => Ok(()),
+        Err(e) => Err(e.locate(r.debug.section, parser.header.tell())),
+    };
+    parser.body = StreamCursor::new(Vec::new(), 0);
+
+    // File size and MD5 need the whole file
+    src.consume(usize::MAX)?;
+    r.filesize = src.end();
+    for chapter in r.chapters.iter_mut() {
+        chapter.bodyend = chapter.bodyend.min(src.end());
+    }
+    parser.md5 = src.hasher.digest().to_hex_lowercase();
+    r.md5 = Some(parser.md5.clone());
+    parsed.map(|_| parser)
+}
+
+/// Same as `Parser::parse_body_sections()`, following chapters through the stream
+fn parse_body_stream<R: Read>(
+    src: &mut Source<R>,
+    r: &mut Record,
+    options: &ParseOptions,
+    nextpos: u32,
+    truncated: bool,
+) -> Result<()> {
+    r.debug.section = Section::Body;
+    src.fill(src.pos + 64)?;
+    let profile = r.profile();
+    if profile.restore_time {
+        // Time in body starts from the restore point
+        r.duration = val!(r.restoretime);
+        if src.buf.is_empty() {
+            r.issavedgame = (!truncated).then_some(true);
+            r.guid = Some(calc_guid(r)?);
+            return Ok(());
+        }
+    }
+    r.issavedgame = Some(false);
+    let mut meta = StreamCursor::new(&src.buf[..], 0);
+    meta.base = src.pos;
+    parse_body_meta(&mut meta, r, options)?;
+
+    let mut start = meta.filepos(meta.tell());
+    let single = !profile.chapters || nextpos == 0;
+    let mut end = if single { usize::MAX } else { nextpos as usize };
+    let mut headerpos = 0;
+    let mut header = None;
+    loop {
+        // A chapter comes from a save command if there is one in the previous chapter
+        let fromsave = r.debug.savecmdfound;
+        r.debug.savecmdfound = false;
+        r.chapters.push(Chapter { headerpos, bodystart: start, bodyend: end, time: r.duration, fromsave, header });
+        if parse_chapter(src, r, options, start, end)? || single {
+            break;
+        }
+
+        src.consume(end)?;
+        src.fill(end.saturating_add(8))?;
+        if src.buf.is_empty() {
+            break;
+        }
+        headerpos = end;
+        start = val!(src.buf.get(0..4).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))) as usize;
+        let next_chapter_pos = val!(src.buf.get(4..8).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]])));
+        header = None;
+        if options.chapter_headers || options.strict {
+            // Positions are not trusted, `inflate` reads more when needed
+            src.fill(start.min(end + 8 + CHUNK).max(end + 8))?;
+            header = match src.inflate(end + 8, options.limits.header) {
+                Ok((x, _, _)) => Some(x).filter(|_| options.chapter_headers),
+                Err(e) if options.strict || matches!(e, Error::LimitExceeded { .. }) => {
+                    return Err(e.locate(Section::Body, end + 8))
+                }
+                Err(_) => None,
+            };
+        }
+        end = chapter_end(headerpos, start, next_chapter_pos)?;
+    }
+
+    finish(r)
+}
+
+/// Parse body from file position `start` to `end` in windows. Returns true after the postgame operation of DE
+fn parse_chapter<R: Read>(
+    src: &mut Source<R>,
+    r: &mut Record,
+    options: &ParseOptions,
+    start: usize,
+    end: usize,
+) -> Result<bool> {
+    let mut pos = start;
+    let mut first = true;
+    loop {
+        src.consume(pos)?;
+        src.fill(end.min(pos.saturating_add(CHUNK + WINDOW_MARGIN)))?;
+        let window_end = src.end().min(end);
+        let last = window_end == end || src.eof;
+        if pos >= window_end {
+            return Ok(false);
+        }
+
+        let mut b = StreamCursor::new(&src.buf[..window_end - src.pos], pos - src.pos);
+        b.base = src.pos;
+        if first {
+            check_first_op(&b, options)?;
+            first = false;
+        }
+        let stop = if last { b.data().len() } else { b.data().len() - WINDOW_MARGIN };
+        match parse_window(&mut b, r, options, &mut (), stop, last)? {
+            WindowEnd::Next if last => return Ok(false),
+            WindowEnd::Next => pos = b.filepos(b.tell()),
+            WindowEnd::Skip(to) => pos = to.min(end),
+            WindowEnd::Done => return Ok(true),
+        }
+    }
+}
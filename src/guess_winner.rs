@@ -1,5 +1,5 @@
+use crate::error::Result;
 use crate::Record;
-use anyhow::Result;
 
 /// Only games with 2 sides are evaluated.
 /// A team is considered to have won if players of the other side all have resigned.
@@ -1,5 +1,5 @@
+use crate::error::Result;
 use crate::Record;
-use anyhow::Result;
 use chksum_hash_md5 as md5;
 
 pub fn calc_guid(rec: &Record) -> Result<String> {
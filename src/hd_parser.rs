@@ -0,0 +1,129 @@
+use crate::cursor::StreamCursor;
+use crate::de_parser::skip_separator;
+use crate::error::{Error, Result, Section};
+use crate::record::*;
+use crate::val;
+use crate::Parser;
+
+/// HD strings have a u16 length before `0x60 0x0a`. Empty strings are `None`
+fn extract_str_hd(h: &mut StreamCursor<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    let str_len = val!(h.get_u16()) as usize;
+    if h.current().get(..2) != Some(&[0x60, 0x0a]) {
+        return Err(Error::OutOfBounds { section: Section::Unknown, offset: 0, reason: "Bad HD string".into() });
+    }
+    h.mov(2);
+    if str_len > h.remain() {
+        return Err(Error::OutOfBounds {
+            section: Section::Unknown,
+            offset: 0,
+            reason: "HD string is too long".into(),
+        });
+    }
+    let raw_str = h.current()[..str_len].to_vec();
+    h.mov(str_len as isize);
+    Ok(if raw_str.is_empty() { None } else { Some(raw_str) })
+}
+
+/// Read the 8 lobby players. Ratings follow the player number in some versions from 1006 on
+fn read_players(h: &mut StreamCursor<Vec<u8>>, r: &mut Record, version: f32, ratings: bool) -> Result<()> {
+    for i in 1..9 {
+        h.mov(4 + 4 + 4 + 2 + 4 + 1); // dlc id, color id, unknown, dat crc, mp game version
+        h.mov(4 + 4); // team index, civ id
+        extract_str_hd(h)?; // ai type
+        h.mov(1); // ai civ name index
+        if version >= 1005.0 {
+            extract_str_hd(h)?; // ai name
+        }
+        extract_str_hd(h)?; // name
+        h.mov(4); // player type
+        r.players[i].steamid = h.get_u64().filter(|x| *x != 0 && *x != u64::MAX);
+        h.mov(4); // player number
+        if ratings {
+            h.mov(4 + 4); // random map and deathmatch ratings
+        }
+    }
+    r.fogofwar = h.get_bool(1);
+    h.mov(1 + 1); // cheat notifications, colored chat
+    skip_separator(h)
+}
+
+impl<T: AsRef<[u8]>> Parser<T> {
+    /// Parse lobby settings and players stored before AI data by HD 5.x. Header cursor should be right after version
+    /// info, it's right before AI data when done. Names, civs and teams are read later from the same places as
+    /// other versions.
+    /// https://github.com/happyleavesaoc/aoc-mgz/blob/master/mgz/header/hd.py
+    pub(crate) fn parse_hd_header(&mut self, r: &mut Record) -> Result<()> {
+        r.debug.section = Section::Settings;
+        let h = &mut self.header;
+
+        let version = val!(h.get_f32());
+        h.mov(4 + 4); // interval version, game options version
+        let num_dlcs = val!(h.get_u32());
+        h.mov(num_dlcs as isize * 4);
+        h.mov(4); // dataset
+        r.difficulty_raw = h.get_i32();
+        h.mov(4); // selected map
+        r.mapid = h.get_u32();
+        r.revealmap_raw = h.get_i32();
+        r.victorytype_raw = h.get_i32();
+        r.resources_raw = h.get_i32();
+        // Ages of HD start from 2(Dark Age), 0 is "Standard"
+        r.startingage_raw = h.get_i32().filter(|x| *x == 0 || *x >= 2).map(|x| if x == 0 { -1 } else { x - 2 });
+        h.mov(4); // ending age
+        r.gametype_raw = h.get_u32().map(|x| x as u8);
+        skip_separator(h)?;
+        if version == 1000.0 {
+            extract_str_hd(h)?; // map name
+            extract_str_hd(h)?;
+        }
+        skip_separator(h)?;
+
+        r.speed_raw = h.get_f32().map(|x| (x * 100.0).round() as u32);
+        h.mov(4); // treaty length
+        r.poplimit = h.get_u32();
+        h.mov(4 + 4 + 4); // number of players, unused player color, victory amount
+        skip_separator(h)?;
+
+        h.mov(2); // trade enabled, team bonus disabled
+        r.randompositions = h.get_bool(1);
+        // All techs of the lobby is the full tech tree option, scenario options are read later
+        r.fulltechtree = h.get_bool(1);
+        h.mov(1); // starting units
+        r.lockteams = h.get_bool(1);
+        r.lockspeed = h.get_bool(1);
+        r.ismultiplayer = h.get_bool(1);
+        r.enablecheats = h.get_bool(1);
+        h.mov(1 + 1 + 1 + 1 + 1); // record game, animals, predators, turbo, shared exploration
+        r.teamtogether = h.get_bool(1);
+        skip_separator(h)?;
+
+        // Only some versions from 1006 on have ratings, the separator after players tells
+        r.debug.section = Section::Lobby;
+        let players = h.tell();
+        if version < 1006.0 || read_players(h, r, version, true).is_err() {
+            h.seek(players);
+            read_players(h, r, version, false)?;
+        }
+
+        h.mov(1); // ranked
+        r.allowspectators = h.get_bool(1);
+        h.mov(4 + 4); // lobby visibility, custom random map crc
+        for _ in 0..3 {
+            extract_str_hd(h)?; // custom scenario, random map and random map scenario files
+            h.mov(8);
+        }
+        h.mov(16); // guid
+        extract_str_hd(h)?; // lobby name
+        if let Some(x) = extract_str_hd(h)? {
+            r.modname = Some(String::from_utf8_lossy(&x).into_owned()); // modded dataset
+        }
+        h.mov(4); // workshop id of modded dataset
+        if version >= 1005.0 {
+            h.mov(4);
+            extract_str_hd(h)?;
+            h.mov(4);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,552 @@
+use crate::error::Warning;
+use crate::Record;
+use phf::phf_map;
+use std::collections::BTreeMap;
+
+/// Localized labels of lobby settings in instructions, for all languages of `ENCODING_MAP`. Values are keys used in
+/// `Record.instructionsettings`. Labels shared by languages are listed once
+static LABELS: phf::Map<&'static str, &'static str> = phf_map! {
+    // en
+    "Map Size" => "mapsize",
+    "Map Type" => "maptype",
+    "Age" => "age",
+    "Resources" => "resources",
+    "Difficulty Level" => "difficulty",
+    "Fixed Positions" => "fixedpositions",
+    "Reveal Map" => "revealmap",
+    "Full Tech Tree" => "fulltechtree",
+    "Enable Cheating" => "enablecheats",
+    "Population Limit" => "poplimit",
+    // zh
+    "地图尺寸" => "mapsize",
+    "地图大小" => "mapsize",
+    "地图类型" => "maptype",
+    "地图类别" => "maptype",
+    "年代" => "age",
+    "时代" => "age",
+    "资源" => "resources",
+    "难度" => "difficulty",
+    "难易度" => "difficulty",
+    "固定地点" => "fixedpositions",
+    "固定位置" => "fixedpositions",
+    "显示地图" => "revealmap",
+    "完全科技树" => "fulltechtree",
+    "完全科技树状图" => "fulltechtree",
+    "完整科技树" => "fulltechtree",
+    "允许作弊" => "enablecheats",
+    "启用密技" => "enablecheats",
+    "人口上限" => "poplimit",
+    // zh-TW, "固定位置" and "人口上限" are the same as zh
+    "地圖大小" => "mapsize",
+    "地圖類型" => "maptype",
+    "地圖類別" => "maptype",
+    "時代" => "age",
+    "資源" => "resources",
+    "難易度" => "difficulty",
+    "顯示地圖" => "revealmap",
+    "完整科技樹" => "fulltechtree",
+    "完全科技樹狀圖" => "fulltechtree",
+    "啟用密技" => "enablecheats",
+    "允許作弊" => "enablecheats",
+    // ja, "時代" is the same as zh-TW, "難易度" and "固定位置" as zh
+    "マップのサイズ" => "mapsize",
+    "マップの種類" => "maptype",
+    "マップ種類" => "maptype",
+    "マップチ種類" => "maptype",
+    "資源の量" => "resources",
+    "マップを表示" => "revealmap",
+    "すべてのテクノロジー" => "fulltechtree",
+    "チートを有効にする" => "enablecheats",
+    "人口の上限" => "poplimit",
+    // ko
+    "지도 크기" => "mapsize",
+    "지도 종류" => "maptype",
+    "시대" => "age",
+    "자원" => "resources",
+    "난이도" => "difficulty",
+    "고정 위치" => "fixedpositions",
+    "지도 표시" => "revealmap",
+    "전체 기술 트리" => "fulltechtree",
+    "치트 사용" => "enablecheats",
+    "인구 한도" => "poplimit",
+    // br
+    "Tamanho do Mapa" => "mapsize",
+    "Tipo de Mapa" => "maptype",
+    "Idade" => "age",
+    "Recursos" => "resources",
+    "Nível de Dificuldade" => "difficulty",
+    "Posições Fixas" => "fixedpositions",
+    "Revelar Mapa" => "revealmap",
+    "Árvore Tecnológica Completa" => "fulltechtree",
+    "Permitir Trapaças" => "enablecheats",
+    "Limite de População" => "poplimit",
+    // de
+    "Kartengröße" => "mapsize",
+    "Kartentyp" => "maptype",
+    "Zeitalter" => "age",
+    "Rohstoffe" => "resources",
+    "Schwierigkeitsgrad" => "difficulty",
+    "Feste Positionen" => "fixedpositions",
+    "Karte aufdecken" => "revealmap",
+    "Vollständiger Technologiebaum" => "fulltechtree",
+    "Cheats aktivieren" => "enablecheats",
+    "Bevölkerungslimit" => "poplimit",
+    // es, "Recursos" is the same as br
+    "Tamaño del mapa" => "mapsize",
+    "Tipo de mapa" => "maptype",
+    "Edad" => "age",
+    "Nivel de dificultad" => "difficulty",
+    "Posiciones fijas" => "fixedpositions",
+    "Revelar mapa" => "revealmap",
+    "Árbol tecnológico completo" => "fulltechtree",
+    "Permitir trucos" => "enablecheats",
+    "Límite de población" => "poplimit",
+    // fr
+    "Taille de la carte" => "mapsize",
+    "Type de carte" => "maptype",
+    "Âge" => "age",
+    "Ressources" => "resources",
+    "Niveau de difficulté" => "difficulty",
+    "Positions fixes" => "fixedpositions",
+    "Révéler la carte" => "revealmap",
+    "Arbre complet des technologies" => "fulltechtree",
+    "Permettre le mode triche" => "enablecheats",
+    "Limite de population" => "poplimit",
+    // it
+    "Dimensioni mappa" => "mapsize",
+    "Tipo di mappa" => "maptype",
+    "Età" => "age",
+    "Risorse" => "resources",
+    "Livello di difficoltà" => "difficulty",
+    "Posizioni fisse" => "fixedpositions",
+    "Rivela mappa" => "revealmap",
+    "Albero delle tecnologie completo" => "fulltechtree",
+    "Abilita trucchi" => "enablecheats",
+    "Limite popolazione" => "poplimit",
+    // nl
+    "Kaartgrootte" => "mapsize",
+    "Kaarttype" => "maptype",
+    "Tijdperk" => "age",
+    "Grondstoffen" => "resources",
+    "Moeilijkheidsgraad" => "difficulty",
+    "Vaste posities" => "fixedpositions",
+    "Kaart onthullen" => "revealmap",
+    "Volledige technologieboom" => "fulltechtree",
+    "Valsspelen inschakelen" => "enablecheats",
+    "Bevolkingslimiet" => "poplimit",
+    // ru
+    "Размер карты" => "mapsize",
+    "Тип карты" => "maptype",
+    "Эпоха" => "age",
+    "Ресурсы" => "resources",
+    "Уровень сложности" => "difficulty",
+    "Фиксированные позиции" => "fixedpositions",
+    "Открыть карту" => "revealmap",
+    "Полное дерево технологий" => "fulltechtree",
+    "Разрешить читы" => "enablecheats",
+    "Предел населения" => "poplimit",
+};
+
+/// "Yes" and "No" of all languages in `LABELS`. "Ja" is both de and nl, "No" is en, es and it
+static YES: [&str; 10] = ["Yes", "是", "はい", "예", "Sim", "Ja", "Sí", "Oui", "Sì", "Да"];
+static NO: [&str; 10] = ["No", "否", "いいえ", "아니요", "아니오", "Não", "Nein", "Non", "Nee", "Нет"];
+
+/// Value of the "Map Size" setting, same as `Record.mapsize_raw`
+static MAP_SIZES: phf::Map<&'static str, u32> = phf_map! {
+    // en
+    "Tiny (2 player)" => 0,
+    "Small (3 player)" => 1,
+    "Medium (4 player)" => 2,
+    "Normal (6 player)" => 3,
+    "Large (8 player)" => 4,
+    "Giant" => 5,
+    // zh
+    "微型 (2 玩家)" => 0,
+    "小型 (3 玩家)" => 1,
+    "中型 (4 玩家)" => 2,
+    "正常 (6 玩家)" => 3,
+    "大型 (8个玩家)" => 4,
+    "大型 (8 名玩家)" => 4,
+    "巨型" => 5,
+    "超小型 (2 名游戏者)" => 0,
+    "小型 (3 名游戏者)" => 1,
+    "中型 (4 名游戏者)" => 2,
+    "标准 (6 名游戏者)" => 3,
+    "大型 (8 名游戏者)" => 4,
+    "超大型" => 5,
+    // zh-TW
+    "極小 (2 名玩家)" => 0,
+    "小 (3 名玩家)" => 1,
+    "中 (4 名玩家)" => 2,
+    "一般 (6 名玩家)" => 3,
+    "大 (8 名玩家)" => 4,
+    "巨大" => 5,
+    // ja, "巨大" is the same as zh-TW
+    "極小 (2 プレイヤー)" => 0,
+    "小 (3 プレイヤー)" => 1,
+    "中 (4 プレイヤー)" => 2,
+    "標準 (6 プレイヤー)" => 3,
+    "大 (8 プレイヤー)" => 4,
+    // ko
+    "아주 작게 (2 플레이어)" => 0,
+    "작게 (3 플레이어)" => 1,
+    "중간 (4 플레이어)" => 2,
+    "보통 (6 플레이어)" => 3,
+    "크게 (8 플레이어)" => 4,
+    "거대" => 5,
+    // br
+    "Minúsculo (2 jogadores)" => 0,
+    "Pequeno (3 jogadores)" => 1,
+    "Médio (4 jogadores)" => 2,
+    "Normal (6 jogadores)" => 3,
+    "Grande (8 jogadores)" => 4,
+    "Gigante" => 5,
+    // de
+    "Winzig (2 Spieler)" => 0,
+    "Klein (3 Spieler)" => 1,
+    "Mittel (4 Spieler)" => 2,
+    "Normal (6 Spieler)" => 3,
+    "Groß (8 Spieler)" => 4,
+    "Riesig" => 5,
+    // es, "Gigante" is the same as br
+    "Diminuto (2 jugadores)" => 0,
+    "Pequeño (3 jugadores)" => 1,
+    "Mediano (4 jugadores)" => 2,
+    "Normal (6 jugadores)" => 3,
+    "Grande (8 jugadores)" => 4,
+    // fr
+    "Minuscule (2 joueurs)" => 0,
+    "Petite (3 joueurs)" => 1,
+    "Moyenne (4 joueurs)" => 2,
+    "Normale (6 joueurs)" => 3,
+    "Grande (8 joueurs)" => 4,
+    "Géante" => 5,
+    // it, "Gigante" is the same as br
+    "Minuscola (2 giocatori)" => 0,
+    "Piccola (3 giocatori)" => 1,
+    "Media (4 giocatori)" => 2,
+    "Normale (6 giocatori)" => 3,
+    "Grande (8 giocatori)" => 4,
+    // nl
+    "Heel klein (2 spelers)" => 0,
+    "Klein (3 spelers)" => 1,
+    "Middelgroot (4 spelers)" => 2,
+    "Normaal (6 spelers)" => 3,
+    "Groot (8 spelers)" => 4,
+    "Gigantisch" => 5,
+    // ru
+    "Крошечная (2 игрока)" => 0,
+    "Маленькая (3 игрока)" => 1,
+    "Средняя (4 игрока)" => 2,
+    "Нормальная (6 игроков)" => 3,
+    "Большая (8 игроков)" => 4,
+    "Гигантская" => 5,
+};
+
+/// Value of the "Difficulty Level" setting, same as `Record.difficulty_raw`
+static DIFFICULTIES: phf::Map<&'static str, i32> = phf_map! {
+    // en, "Standard" is also de, fr and it
+    "Hardest" => 0,
+    "Hard" => 1,
+    "Moderate" => 2,
+    "Standard" => 3,
+    "Easiest" => 4,
+    // zh, "中等" is also zh-TW
+    "最难" => 0,
+    "难" => 1,
+    "中等" => 2,
+    "标准" => 3,
+    "最易" => 4,
+    // zh-TW, "標準" is also ja
+    "最難" => 0,
+    "難" => 1,
+    "標準" => 3,
+    "最簡單" => 4,
+    // ja
+    "最も難しい" => 0,
+    "難しい" => 1,
+    "普通" => 2,
+    "最も易しい" => 4,
+    // ko
+    "가장 어려움" => 0,
+    "어려움" => 1,
+    "보통" => 2,
+    "표준" => 3,
+    "가장 쉬움" => 4,
+    // br, "Difícil" and "Moderado" are also es
+    "Muito Difícil" => 0,
+    "Difícil" => 1,
+    "Moderado" => 2,
+    "Padrão" => 3,
+    "Muito Fácil" => 4,
+    // de
+    "Sehr schwer" => 0,
+    "Schwer" => 1,
+    "Mittel" => 2,
+    "Sehr leicht" => 4,
+    // es
+    "Muy difícil" => 0,
+    "Estándar" => 3,
+    "Muy fácil" => 4,
+    // fr, "Difficile" is also it
+    "Très difficile" => 0,
+    "Difficile" => 1,
+    "Modéré" => 2,
+    "Très facile" => 4,
+    // it
+    "Difficilissimo" => 0,
+    "Moderato" => 2,
+    "Facilissimo" => 4,
+    // nl
+    "Zeer moeilijk" => 0,
+    "Moeilijk" => 1,
+    "Gemiddeld" => 2,
+    "Standaard" => 3,
+    "Zeer makkelijk" => 4,
+    // ru
+    "Очень сложный" => 0,
+    "Сложный" => 1,
+    "Средний" => 2,
+    "Стандартный" => 3,
+    "Очень легкий" => 4,
+};
+
+/// Value of the "Age" setting, same as `Record.startingage_raw`
+static AGES: phf::Map<&'static str, i32> = phf_map! {
+    // en, "Standard" is also de, fr and it
+    "Standard" => -1,
+    "Dark Age" => 0,
+    "Feudal Age" => 1,
+    "Castle Age" => 2,
+    "Imperial Age" => 3,
+    "Post-Imperial Age" => 4,
+    // zh
+    "标准" => -1,
+    "黑暗时代" => 0,
+    "封建时代" => 1,
+    "城堡时代" => 2,
+    "帝王时代" => 3,
+    "后帝王时代" => 4,
+    // zh-TW, "標準" is also ja
+    "標準" => -1,
+    "黑暗時代" => 0,
+    "封建時代" => 1,
+    "城堡時代" => 2,
+    "帝王時代" => 3,
+    "後帝王時代" => 4,
+    // ja
+    "暗黒の時代" => 0,
+    "領主の時代" => 1,
+    "城主の時代" => 2,
+    "帝王の時代" => 3,
+    "帝王の時代以降" => 4,
+    // ko
+    "표준" => -1,
+    "암흑 시대" => 0,
+    "봉건 시대" => 1,
+    "성주 시대" => 2,
+    "왕정 시대" => 3,
+    "왕정 시대 이후" => 4,
+    // br
+    "Padrão" => -1,
+    "Idade das Trevas" => 0,
+    "Idade Feudal" => 1,
+    "Idade dos Castelos" => 2,
+    "Idade Imperial" => 3,
+    "Idade Pós-Imperial" => 4,
+    // de
+    "Dunkle Zeit" => 0,
+    "Feudalzeit" => 1,
+    "Ritterzeit" => 2,
+    "Imperialzeit" => 3,
+    "Post-Imperialzeit" => 4,
+    // es
+    "Estándar" => -1,
+    "Alta Edad Media" => 0,
+    "Edad Feudal" => 1,
+    "Edad de los Castillos" => 2,
+    "Edad Imperial" => 3,
+    "Edad Postimperial" => 4,
+    // fr
+    "Âge sombre" => 0,
+    "Âge féodal" => 1,
+    "Âge des châteaux" => 2,
+    "Âge impérial" => 3,
+    "Âge post-impérial" => 4,
+    // it
+    "Alto Medioevo" => 0,
+    "Età feudale" => 1,
+    "Età dei castelli" => 2,
+    "Età imperiale" => 3,
+    "Età post-imperiale" => 4,
+    // nl
+    "Standaard" => -1,
+    "Donkere Middeleeuwen" => 0,
+    "Feodale Tijd" => 1,
+    "Kasteeltijd" => 2,
+    "Keizertijd" => 3,
+    "Post-Keizertijd" => 4,
+    // ru
+    "Стандарт" => -1,
+    "Тёмные века" => 0,
+    "Темные века" => 0,
+    "Феодальная эпоха" => 1,
+    "Эпоха замков" => 2,
+    "Имперская эпоха" => 3,
+    "Постимперская эпоха" => 4,
+};
+
+/// Value of the "Resources" setting, same as `Record.resources_raw`
+static RESOURCES: phf::Map<&'static str, i32> = phf_map! {
+    // en, "Standard" is also de, fr and it
+    "Standard" => 0,
+    "Low" => 1,
+    "Medium" => 2,
+    "High" => 3,
+    // zh
+    "标准" => 0,
+    "低" => 1,
+    "中" => 2,
+    "中等" => 2,
+    "高" => 3,
+    // zh-TW and ja
+    "標準" => 0,
+    "少ない" => 1,
+    "普通" => 2,
+    "多い" => 3,
+    // ko
+    "표준" => 0,
+    "낮음" => 1,
+    "중간" => 2,
+    "높음" => 3,
+    // br, es and it, "Alto" is all of them
+    "Padrão" => 0,
+    "Estándar" => 0,
+    "Baixo" => 1,
+    "Bajo" => 1,
+    "Basso" => 1,
+    "Médio" => 2,
+    "Medio" => 2,
+    "Alto" => 3,
+    // de
+    "Niedrig" => 1,
+    "Mittel" => 2,
+    "Hoch" => 3,
+    // fr
+    "Faible" => 1,
+    "Moyen" => 2,
+    "Élevé" => 3,
+    // nl
+    "Standaard" => 0,
+    "Laag" => 1,
+    "Gemiddeld" => 2,
+    "Hoog" => 3,
+    // ru
+    "Стандарт" => 0,
+    "Низкий" => 1,
+    "Средний" => 2,
+    "Высокий" => 3,
+};
+
+/// Parse lobby settings in decoded instructions into a key/value map.
+/// Each line looks like `label: value`, labels are localized. Values end at the first `'\0'`, lines with unknown
+/// labels are ignored
+pub fn parse_instructions(text: &str) -> BTreeMap<String, String> {
+    let mut settings = BTreeMap::new();
+    for line in text.lines() {
+        let Some((label, value)) = line.split_once([':', '：']) else {
+            continue;
+        };
+        if let Some(key) = LABELS.get(label.trim()) {
+            // UP1.5 appends seed and map script after population limit, split by '\0'
+            let value = value.split('\0').next().unwrap_or_default().trim();
+            if !value.is_empty() {
+                settings.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    settings
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if YES.contains(&value) {
+        Some(true)
+    } else if NO.contains(&value) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Value of `key` in `settings` read by `parse`. Keys of values `parse` doesn't know are added to `unknown`
+fn lookup<T>(
+    settings: &BTreeMap<String, String>,
+    key: &'static str,
+    parse: impl Fn(&str) -> Option<T>,
+    unknown: &mut Vec<&'static str>,
+) -> Option<T> {
+    let value = settings.get(key)?;
+    let parsed = parse(value);
+    if parsed.is_none() {
+        unknown.push(key);
+    }
+    parsed
+}
+
+/// Fill `field` with `parsed` from instructions if the header doesn't have it. Returns false if both have it and
+/// they differ
+fn merge<T: PartialEq>(field: &mut Option<T>, parsed: Option<T>) -> bool {
+    match (field.as_ref(), parsed) {
+        (None, parsed) => {
+            *field = parsed;
+            true
+        }
+        (Some(x), Some(y)) => *x == y,
+        (Some(_), None) => true,
+    }
+}
+
+impl Record {
+    /// Parse instructions, fill fields the binary parser could not find and warn about those that differ or have
+    /// unknown values
+    pub(crate) fn apply_instructions(&mut self) {
+        let Some(raw) = self.instructions_raw.as_ref() else {
+            return;
+        };
+        let (decoded, _, _) = self.encoding().decode(raw);
+        let settings = parse_instructions(&decoded);
+        let mut unknown = Vec::new();
+        let mapsize = lookup(&settings, "mapsize", |x| MAP_SIZES.get(x).copied(), &mut unknown);
+        let difficulty = lookup(&settings, "difficulty", |x| DIFFICULTIES.get(x).copied(), &mut unknown);
+        // Reveal map of the header also tells "Explored" from "All Visible", it's not filled from instructions
+        let revealmap = lookup(&settings, "revealmap", parse_bool, &mut unknown);
+        let resources = lookup(&settings, "resources", |x| RESOURCES.get(x).copied(), &mut unknown);
+        let age = lookup(&settings, "age", |x| AGES.get(x).copied(), &mut unknown);
+        let fixedpositions = lookup(&settings, "fixedpositions", parse_bool, &mut unknown);
+        let fulltechtree = lookup(&settings, "fulltechtree", parse_bool, &mut unknown);
+        let enablecheats = lookup(&settings, "enablecheats", parse_bool, &mut unknown);
+        let poplimit = lookup(&settings, "poplimit", |x| x.parse().ok(), &mut unknown);
+
+        self.maptype = settings.get("maptype").cloned();
+        let agreements = [
+            ("mapsize", merge(&mut self.mapsize_raw, mapsize)),
+            ("difficulty", merge(&mut self.difficulty_raw, difficulty)),
+            ("revealmap", !matches!((self.revealmap_raw, revealmap), (Some(x), Some(y)) if (x != 0) != y)),
+            ("resources", merge(&mut self.resources_raw, resources)),
+            ("age", merge(&mut self.startingage_raw, age)),
+            ("fixedpositions", merge(&mut self.fixedpositions, fixedpositions)),
+            ("fulltechtree", merge(&mut self.fulltechtree, fulltechtree)),
+            ("enablecheats", merge(&mut self.enablecheats, enablecheats)),
+            ("poplimit", merge(&mut self.poplimit, poplimit)),
+        ];
+        for setting in unknown {
+            self.warnings.push(Warning::UnknownInstructionValue { setting, value: settings[setting].clone() });
+        }
+        for (setting, agreed) in agreements {
+            if !agreed {
+                self.warnings.push(Warning::InstructionsMismatch { setting, instructions: settings[setting].clone() });
+            }
+        }
+        self.instructionsettings = settings;
+    }
+}
@@ -0,0 +1,479 @@
+use crate::cursor::StreamCursor;
+use crate::profile::VersionProfile;
+
+/// i32 -99, starts victory, disabled techs and game settings
+pub(crate) const SEPARATOR: [u8; 4] = [0x9d, 0xff, 0xff, 0xff];
+
+/// f64 1.6, trigger version right before triggers
+pub(crate) const TRIGGER_VERSION: [u8; 8] = [0x9a, 0x99, 0x99, 0x99, 0x99, 0x99, 0xf9, 0x3f];
+
+/// Victory conditions(44) and diplomacy(12544)
+const VICTORY_LEN: usize = 44 + 12544;
+/// Disabled techs, units and buildings, all techs and starting ages
+pub(crate) const DISABLEDTECHS_LEN: usize = 5456;
+
+/// Header of the first object list of a player: u8 11, u32 2048 and u32 512. Player data between resources and
+/// object lists is not parsed, object lists are found by it
+const OBJECTS_START: [u8; 9] = [0x0b, 0x00, 0x08, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00];
+
+/// Header and end of object lists
+const OBJECTS_MARKER: u8 = 0x0b;
+
+/// Path data of moving objects
+const PATH_LEN: usize = 44;
+
+/// Actions nested deeper are not walked
+const MAX_ACTION_DEPTH: usize = 16;
+
+/// u32s after strings of DE lobby settings that are followed by another u32
+const DE_STRING_MARKERS: [u32; 8] = [3, 21, 23, 42, 44, 45, 46, 47];
+
+/// u32 0xdeaddead before the map of DE
+const DE_MAP_MARKER: [u8; 4] = [0xad, 0xde, 0xad, 0xde];
+
+/// Data between object lists of the last player and scenario in records of random maps: 21 bytes and this much for
+/// each player but GAIA. Scenarios may have more
+const PLAYERS_TAIL: usize = 21;
+const PLAYER_TAIL: usize = 1817;
+
+/// Map tiles of DE: terrain, unknown, terrain again, elevation and 6 unknown bytes
+pub(crate) const DE_TILE_LEN: usize = 10;
+
+/// Positions of sections in header
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Sections {
+    /// Names in player init data by player index, GAIA is 0
+    pub players: [Option<usize>; 9],
+    pub scenario: usize,
+    pub victory: usize,
+    pub disabledtechs: usize,
+    pub settings: usize,
+    /// After the trigger version
+    pub trigger: usize,
+    /// Scenario is not at its usual distance after player init data, it's found by its version
+    pub scenariosearched: bool,
+}
+
+/// Locate sections by walking header from player init data at `start` to triggers. Object lists of players are walked
+/// by their layout in each version. Scenario is expected at `PLAYERS_TAIL` and `PLAYER_TAIL` after the last player,
+/// or at the first scenario version after it. `None` if the walk fails
+pub(crate) fn walk_sections(
+    h: &mut StreamCursor<Vec<u8>>,
+    profile: &VersionProfile,
+    start: usize,
+    totalplayers: usize,
+) -> Option<Sections> {
+    let mut players = [None; 9];
+    let mut players_end = start;
+    h.seek(start);
+    for pos in players.iter_mut().take(totalplayers) {
+        *pos = Some(walk_player(h, profile, totalplayers)?);
+        players_end = h.tell();
+        skip(h, profile.init_skip)?;
+    }
+
+    h.seek(players_end + PLAYERS_TAIL + PLAYER_TAIL * totalplayers.saturating_sub(1));
+    if let Some(sections) = walk_from_scenario(h, profile) {
+        return Some(Sections { players, ..sections });
+    }
+
+    let pos = h.find(profile.scenario_version.to_vec(), players_end + 4..h.data().len())?;
+    h.seek(pos - 4);
+    let sections = walk_from_scenario(h, profile)?;
+    Some(Sections { players, scenariosearched: true, ..sections })
+}
+
+/// Walk a player in player init data: type(1), unknown(1), diplomacy from others(1 each), diplomacy to
+/// others(9 * 4), unknown(5), name, unknown(1), resources, unknown(1), player data and object lists. Returns position
+/// of name
+fn walk_player(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile, totalplayers: usize) -> Option<usize> {
+    skip(h, 2 + totalplayers + 9 * 4 + 5)?;
+    let name = h.tell();
+    let namelen = h.get_u16()? as usize;
+    if namelen == 0 || h.current().get(namelen - 1) != Some(&0) {
+        return None;
+    }
+    skip(h, namelen + 1)?;
+    let num_resources = h.get_u32()? as usize;
+    skip(h, num_resources.checked_mul(4)?.checked_add(1)?)?;
+    let objects = h.find(OBJECTS_START.to_vec(), h.tell()..h.data().len())?;
+    h.seek(objects);
+    walk_objects(h, profile)?;
+    Some(name)
+}
+
+/// Walk 3 object lists, each of a header(9), objects and a zero byte, and the end marker after them. Object is
+/// type(1), owner(1) and data of the type
+fn walk_objects(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    for _ in 0..3 {
+        if h.get_u8()? != OBJECTS_MARKER {
+            return None;
+        }
+        skip(h, 8)?;
+        loop {
+            let kind = h.get_u8()?;
+            if kind == 0 {
+                break;
+            }
+            skip(h, 1)?;
+            match kind {
+                10 => walk_static(h, profile)?,
+                20 => walk_animated(h, profile)?,
+                30 => walk_moving(h, profile)?,
+                70 => walk_combat(h, profile)?,
+                80 => {
+                    walk_combat(h, profile)?;
+                    skip(h, profile.building_tail)?;
+                }
+                // Other types are not seen in player init data
+                _ => return None,
+            }
+        }
+    }
+    (h.get_u8()? == OBJECTS_MARKER).then_some(())
+}
+
+/// Unit type id, sprite, garrisoned in, hit points, states, object id, facet, position, offsets, resource, pathing
+/// groups, group id and sprites
+fn walk_static(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    skip(h, 2 + 2 + 4 + 4 + 4 + 4 + 1 + 12 + 8 + profile.static_group)?;
+    skip(h, 2 + 4 + 4)?; // resource type, amount and unknown
+    skip_list(h, 4)?;
+    skip(h, profile.static_skip + 4 + 1)?;
+    if h.get_u8()? == 0 {
+        return Some(());
+    }
+    loop {
+        match h.get_u8()? {
+            0 => return Some(()),
+            1 => skip(h, 16)?,
+            2 => skip(h, 16 + 17)?,
+            _ => return None,
+        }
+    }
+}
+
+/// Static object and an unknown float
+fn walk_animated(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    walk_static(h, profile)?;
+    skip(h, 4)
+}
+
+/// Animated object, movement, path data, future path, movement data and waypoints
+fn walk_moving(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    walk_animated(h, profile)?;
+    skip(h, 4 + 12 + 4 + 12 + 8 + 3)?;
+    skip_list(h, PATH_LEN)?;
+    for len in [PATH_LEN, 24] {
+        if h.get_u32()? != 0 {
+            skip(h, len)?;
+        }
+    }
+    skip(h, 12 + 24 + 4)?;
+    skip_list(h, 12)?;
+    skip(h, 4 + 12 + 4)
+}
+
+/// Moving object, waiting and command flags, then actions
+fn walk_action(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    walk_moving(h, profile)?;
+    skip(h, 2 + profile.action_skip)?;
+    walk_actions(h, profile, 0)
+}
+
+/// Actions up to type 0: type, state, targets, position, timer, flags, sub-actions, sprite and data of the type.
+/// Only actions of idle units at game start are known: move(1), fly(10) and those of animals(107)
+fn walk_actions(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile, depth: usize) -> Option<()> {
+    if depth > MAX_ACTION_DEPTH {
+        return None;
+    }
+    loop {
+        let kind = h.get_u16()?;
+        if kind == 0 {
+            return Some(());
+        }
+        skip(h, profile.action_state + 16 + 12 + 4 + 1 + 2 + 1)?;
+        walk_actions(h, profile, depth + 1)?;
+        skip(h, 2)?;
+        match kind {
+            1 => skip(h, 4)?,
+            10 | 107 => {}
+            _ => return None,
+        }
+    }
+}
+
+/// Action object, its own unit type if any, unit AI if any and unknown bytes
+fn walk_combat(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    walk_action(h, profile)?;
+    skip(h, 16)?;
+    if h.get_u8()? != 0 {
+        walk_unit_type(h, profile)?;
+    }
+    skip(h, profile.combat_skip)?;
+    if h.get_u32()? != 0 {
+        walk_unit_ai(h, profile)?;
+    }
+    skip(h, profile.combat_tail)
+}
+
+/// Unit type as in the data file, of types up to combat(70). Buildings(80) are not known
+fn walk_unit_type(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    let kind = h.get_u8()?;
+    if !matches!(kind, 10 | 20 | 30 | 40 | 50 | 70) {
+        return None;
+    }
+    let namelen = h.get_u16()? as usize;
+    // Ids, graphics, hit points, sizes, sounds, placement, flags, help strings and classes up to civilization
+    skip(h, 2 + 2 + 2 + 2 + 4 + 4 + 1 + 2 + 4 + 1 + 12 + 2 + 2 + 2 + 1 + 1 + 2 + 1 + 2 + 1 + 1 + 4 + 4 + 8 + 1)?;
+    skip(h, 1 + 2 + 1 + 2 + 4 + 1 + 1 + 1 + 1 + 1 + 4 + 1 + 4 + 4 + 4 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1)?;
+    skip(h, profile.unit_type_skip + 12 + 3 * 7)?; // outline, resource storages
+    let num_damage_graphics = h.get_u8()? as usize;
+    skip(h, num_damage_graphics * 4)?;
+    skip(h, 2 + 2 + 1 + 1 + namelen + 2 + 2)?; // sounds, attack reaction, terrain, name, copy and base id
+    if kind >= 20 {
+        skip(h, 4)?; // speed
+    }
+    if kind >= 30 {
+        skip(h, 4 + 4 + 1 + 2 + 1 + 4 + 1 + 20)?;
+    }
+    if kind >= 40 {
+        skip(h, 2 + 4 + 4 + 4 + 1 + 2 + 2 + 1)?;
+    }
+    if kind >= 50 {
+        skip(h, profile.base_armor)?;
+        for _ in 0..2 {
+            let num = h.get_u16()? as usize; // attacks, armours
+            skip(h, num * 4)?;
+        }
+        skip(h, 2 + 4 + 4 + 4 + 2 + 2 + 1 + 2 + 12 + 1 + 4 + 4 + 2 + 2 + 2 + 4 + 4)?;
+    }
+    if kind >= 70 {
+        skip(h, 18 + 2 + 2 + 1 + 4 + 4 + 1 + 1 + 4 + 4 + 1 + 12 + 4 + 4 + 1 + 2)?;
+    }
+    Some(())
+}
+
+/// Unit AI. Layouts of orders, notifications, order history and retargets are not known, they must be empty
+fn walk_unit_ai(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<()> {
+    skip(h, 24 + 12 + 4 + 16 + 4 * 7 + 4 + 4 + 16)?;
+    empty_list(h)?;
+    empty_list(h)?;
+    skip_list(h, 4)?; // attacking units
+    skip(h, 1 + 1 + 8 + 4 + 1 + 4)?;
+    skip_list(h, 16)?; // patrol path
+    skip(h, 4)?;
+    empty_list(h)?;
+    skip(h, 8)?;
+    empty_list(h)?;
+    skip(h, 4 + 1 + profile.unit_ai_skip)
+}
+
+fn walk_from_scenario(h: &mut StreamCursor<Vec<u8>>, profile: &VersionProfile) -> Option<Sections> {
+    let scenario = h.tell();
+    // Unknown u32, scenario version, player names(16 * 256), string ids of player names(16 * 4),
+    // player info(16 * 16), unknown(5), elapsed time(4)
+    skip(h, 4 + 4 + 16 * 256 + 16 * 4 + 16 * 16 + 5 + 4)?;
+    skip_str16(h)?; // scenario filename
+    skip(h, 4 * profile.string_ids)?;
+    for _ in 0..profile.messages {
+        skip_str16(h)?;
+    }
+    // Cinematics of pregame, victory and loss, background filename
+    for _ in 0..4 {
+        skip_str16(h)?;
+    }
+    skip_bitmap(h)?;
+
+    // Unknown strings, AI names, AI files and AI types of 16 players
+    for _ in 0..32 + 16 {
+        skip_str16(h)?;
+    }
+    for _ in 0..16 {
+        skip(h, 8)?;
+        skip_str32(h)?;
+    }
+    skip(h, 16)?;
+    separator(h)?;
+    skip(h, 16 * 6 * 4)?; // resources
+
+    let victory = h.tell();
+    separator(h)?;
+    let disabledtechs = victory + VICTORY_LEN;
+    h.seek(disabledtechs);
+    separator(h)?;
+    let settings = disabledtechs + DISABLEDTECHS_LEN;
+    h.seek(settings);
+    separator(h)?;
+
+    // Unknown(8), map id, difficulty, lock teams, then index, type and name of 9 players
+    skip(h, 8 + if profile.mapid { 4 } else { 0 } + 4 + 4)?;
+    for _ in 0..9 {
+        skip(h, 8)?;
+        skip_str32(h)?;
+    }
+
+    // Entries of id, count and triples of floats, each with a trailer of 20 bytes
+    skip(h, profile.settings_skip)?;
+    for _ in 0..h.get_u32()? {
+        skip(h, 4)?;
+        let num_triples = h.get_u32()? as usize;
+        skip(h, num_triples.checked_mul(12)?.checked_add(20)?)?;
+    }
+    skip(h, profile.trigger_skip)?;
+    if !h.current().starts_with(&TRIGGER_VERSION) {
+        return None;
+    }
+    let trigger = h.tell() + TRIGGER_VERSION.len();
+    Some(Sections { scenario, victory, disabledtechs, settings, trigger, ..Default::default() })
+}
+
+/// Map of DE and the modded dataset before it
+pub(crate) struct DeMap {
+    pub size: i32,
+    /// Position of tiles
+    pub tiles: usize,
+    pub dataset: Option<Vec<u8>>,
+}
+
+/// Walk DE header from the end of lobby settings to the map: lobby options, strings, AI files, lobby name, modded
+/// dataset, AI and replay, as laid out in save version 63.x. `None` if the walk fails or AI scripts are included,
+/// which are not laid out as in older versions
+/// https://github.com/happyleavesaoc/aoc-mgz/blob/master/mgz/header/de.py
+pub(crate) fn walk_de_map(h: &mut StreamCursor<Vec<u8>>) -> Option<DeMap> {
+    // Lobby visibility(4), hidden civs(1), matchmaking(1), spectator delay(4), scenario civ(1), unknown(4)
+    skip(h, 4 + 1 + 1 + 4 + 1 + 4)?;
+    // Each string is followed by u32s, the last of which is not one of the markers
+    for _ in 0..23 {
+        skip_str_de(h)?;
+        while DE_STRING_MARKERS.contains(&h.get_u32()?) {}
+    }
+    let num_ai_files = h.get_u32()? as usize;
+    skip(h, 4)?;
+    for _ in 0..num_ai_files {
+        skip(h, 4)?;
+        skip_str_de(h)?;
+        skip(h, 4)?;
+    }
+    skip(h, 8 + 16)?; // unknown, guid
+    skip_str_de(h)?; // lobby name
+    skip(h, 8)?;
+    let dataset = read_str_de(h)?; // modded dataset
+    skip(h, 19 + 5 + 9 + 1 + 8 + 21 + 4 + 8 + 3 + 8 + 1 + 5)?;
+    skip_str_de(h)?;
+    skip(h, 5 + 1 + 2 + 4 + 4)?; // unknown, timestamp, unknown
+
+    if h.get_u32()? != 0 {
+        return None; // include AI
+    }
+    // Replay: times and game speed(45), recorder(2), total players(1), instant build(1), cheats(1), unknown(44)
+    skip(h, 45 + 2 + 1 + 1 + 1 + 44)?;
+    if !h.current().starts_with(&DE_MAP_MARKER) {
+        return None;
+    }
+    skip(h, DE_MAP_MARKER.len() + 4)?;
+    let (size, tiles) = de_map_at(h, h.tell())?;
+    Some(DeMap { size, tiles, dataset: (!dataset.is_empty()).then_some(dataset) })
+}
+
+/// Square map size, map zones, tiles, obstructions and a visibility map of the same size
+fn de_map_at(h: &mut StreamCursor<Vec<u8>>, pos: usize) -> Option<(i32, usize)> {
+    h.seek(pos);
+    let size = h.get_i32()?;
+    if !(1..=10000).contains(&size) || h.get_i32()? != size {
+        return None;
+    }
+    let num_tiles = (size * size) as usize;
+    for _ in 0..h.get_u32()? {
+        skip(h, 2048 + num_tiles * 2)?;
+        let num_floats = h.get_u32()? as usize;
+        skip(h, num_floats.checked_mul(4)?.checked_add(4)?)?;
+    }
+    skip(h, 2)?; // all visible, fog of war
+    let tiles = h.tell();
+    skip(h, num_tiles * DE_TILE_LEN)?;
+    let num_data = h.get_u32()? as usize;
+    skip(h, num_data.checked_mul(4)?.checked_add(4)?)?;
+    for _ in 0..num_data {
+        let num_obstructions = h.get_u32()? as usize;
+        skip(h, num_obstructions.checked_mul(8)?)?;
+    }
+    if h.get_i32()? != size || h.get_i32()? != size {
+        return None;
+    }
+    Some((size, tiles))
+}
+
+fn skip(h: &mut StreamCursor<Vec<u8>>, len: usize) -> Option<()> {
+    if h.remain() < len {
+        return None;
+    }
+    h.mov(len as isize);
+    Some(())
+}
+
+/// u32 count and entries of `len`
+fn skip_list(h: &mut StreamCursor<Vec<u8>>, len: usize) -> Option<()> {
+    let num = h.get_u32()? as usize;
+    skip(h, num.checked_mul(len)?)
+}
+
+fn empty_list(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    (h.get_u32()? == 0).then_some(())
+}
+
+fn skip_str16(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    let len = h.get_u16()?;
+    skip(h, len as usize)
+}
+
+/// DE strings are prefixed with `0x60 0x0a` and a u16 length
+fn skip_str_de(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    if !h.current().starts_with(&[0x60, 0x0a]) {
+        return None;
+    }
+    skip(h, 2)?;
+    skip_str16(h)
+}
+
+fn read_str_de(h: &mut StreamCursor<Vec<u8>>) -> Option<Vec<u8>> {
+    let start = h.tell() + 4;
+    skip_str_de(h)?;
+    Some(h.data()[start..h.tell()].to_vec())
+}
+
+fn skip_str32(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    let len = h.get_u32()?;
+    skip(h, len as usize)
+}
+
+fn separator(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    if !h.current().starts_with(&SEPARATOR) {
+        return None;
+    }
+    skip(h, SEPARATOR.len())
+}
+
+/// Background bitmap: included(4), width(4), height(4), orientation(2), then BITMAPINFOHEADER, palette and pixels
+/// if included
+fn skip_bitmap(h: &mut StreamCursor<Vec<u8>>) -> Option<()> {
+    let included = h.get_u32()?;
+    skip(h, 4 + 4 + 2)?;
+    if included == 0 {
+        return Some(());
+    }
+    let info = h.current();
+    let at = |pos: usize| info.get(pos..pos + 4).map(|x| u32::from_le_bytes(x.try_into().unwrap()));
+    let size = at(0)? as usize;
+    let width = at(4)? as i32;
+    let height = at(8)? as i32;
+    let bitcount = at(12)? >> 16;
+    let image_size = at(20)? as usize;
+    let colors = match at(32)? {
+        0 if bitcount <= 8 => 1usize << bitcount,
+        x => x as usize,
+    };
+    let row = (width.unsigned_abs() as usize * bitcount as usize).div_ceil(32) * 4;
+    let pixels = if image_size > 0 { image_size } else { row.checked_mul(height.unsigned_abs() as usize)? };
+    skip(h, size.checked_add(colors.checked_mul(4)?)?.checked_add(pixels)?)
+}
@@ -1,17 +1,37 @@
 #![doc = include_str!("../README.md")]
 
+mod batch;
+pub use batch::{parse_many, BatchStats};
 mod cursor;
+mod datamod;
+mod de_parser;
 mod draw_map;
-pub use draw_map::draw_map;
+pub use draw_map::{draw_map, map_image};
+mod error;
+pub use error::{Error, Section, Warning};
+mod events;
+pub use events::{BodyEvent, CommandKind, Events};
 mod from_file;
-pub use from_file::from_file;
+pub use from_file::{from_file, from_file_mmap};
+pub use memmap2::Mmap;
+mod from_reader;
+pub use from_reader::from_reader;
+mod layout;
 mod mapcolors;
 mod parser;
-pub use parser::Parser;
+pub use parser::{Limits, ParseOptions, Parser};
+mod probe;
+pub use probe::{probe, Probe};
+mod profile;
 mod record;
 pub use record::*;
 mod guess_winner;
 mod guid;
+mod hd_parser;
+mod instructions;
+pub use instructions::parse_instructions;
 mod render;
 mod translations;
+mod visitor;
+pub use visitor::BodyVisitor;
 mod body_parser;
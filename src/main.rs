@@ -8,7 +8,8 @@ use std::time::UNIX_EPOCH;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Path to the record file. Only AoK(.mgl)/AoC(.mgx)/UP1.5(.mgz) are supported.
+    /// Path to the record file. Only AoK(.mgl)/AoC(.mgx)/UP1.5(.mgz)/HD(.mgx2)/DE save version 63.x(.aoe2record)
+    /// are supported.
     record_path: PathBuf,
 
     /// Generate a map image as a .png image.   
@@ -31,6 +32,10 @@ struct Cli {
     /// Dump body section to specified file
     #[arg(long)]
     body: Option<PathBuf>,
+
+    /// Fail on unexpected data in body instead of skipping it
+    #[arg(long)]
+    strict: bool,
 }
 
 fn main() {
@@ -50,7 +55,8 @@ fn main() {
     let last_modified = metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_millis();
 
     let mut rec = mgx::Record::new(filename.into_owned(), buffer.len(), last_modified);
-    let mut parser = mgx::Parser::new(buffer).unwrap();
+    let options = mgx::ParseOptions { strict: cli.strict, ..Default::default() };
+    let mut parser = mgx::Parser::with_options(buffer, options).unwrap();
 
     if let Some(header_path) = cli.header {
         parser.dump_header(header_path.to_str().unwrap()).unwrap_or_else(|e| {
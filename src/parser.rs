@@ -1,61 +1,248 @@
 use crate::body_parser::parse_body;
 use crate::cursor::StreamCursor;
+use crate::error::{Error, Result, Section, Warning};
+use crate::events::Events;
 use crate::guess_winner::guess;
 use crate::guid::calc_guid;
+use crate::layout::{walk_sections, DISABLEDTECHS_LEN, SEPARATOR, TRIGGER_VERSION};
+use crate::probe::detect_version;
+use crate::profile::{unsupported, BodyMeta, VersionProfile};
 use crate::record::*;
 use crate::val;
-use anyhow::{bail, Ok, Result};
+use crate::visitor::BodyVisitor;
 use chksum_hash_md5 as md5;
-use flate2::read::ZlibDecoder;
-use flate2::Decompress;
-use std::io::Read;
+use flate2::{Decompress, FlushDecompress, Status};
+
+/// Decompress as much of a header of the recorded game or its chapters as possible, up to `limit` bytes.
+/// Returns the header, its compressed size and whether the compressed stream is complete.
+/// A file cut off in the header still gives the part before the cut
+pub(crate) fn inflate(src: &[u8], limit: usize) -> Result<(Vec<u8>, usize, bool)> {
+    let mut decompress = Decompress::new(false);
+    let mut buffer = Vec::with_capacity(src.len().saturating_mul(4).min(limit.saturating_add(1)));
+    loop {
+        if buffer.len() > limit {
+            return Err(Error::LimitExceeded {
+                section: Section::Unknown,
+                offset: 0,
+                limit: "header size",
+                max: limit,
+            });
+        }
+        if buffer.len() == buffer.capacity() {
+            // One byte more than the limit is enough to tell it's exceeded
+            let budget = limit.saturating_add(1) - buffer.len();
+            buffer.reserve_exact(buffer.capacity().max(0x10000).min(budget));
+        }
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress_vec(&src[total_in as usize..], &mut buffer, FlushDecompress::None)
+            .map_err(|e| Error::Decompression { section: Section::Unknown, offset: 0, reason: e.to_string() })?;
+        if status == Status::StreamEnd {
+            return Ok((buffer, decompress.total_in() as usize, true));
+        }
+        // No progress with room in the buffer, the input is used up
+        if decompress.total_in() == total_in && decompress.total_out() == total_out {
+            return Ok((buffer, total_in as usize, false));
+        }
+    }
+}
+
+/// Inflate a header. It starts with a printable version string and save version, so a wrong start is rejected
+pub(crate) fn inflate_header(src: &[u8], limit: usize) -> Result<(Vec<u8>, usize, bool)> {
+    let inflated = inflate(src, limit)?;
+    if !has_version(&inflated.0) {
+        return Err(Error::Decompression {
+            section: Section::Unknown,
+            offset: 0,
+            reason: "No version string in header".into(),
+        });
+    }
+    Ok(inflated)
+}
+
+/// Whether a decompressed header starts with a printable version string and a save version
+pub(crate) fn has_version(header: &[u8]) -> bool {
+    header.len() >= 12 && header[..7].iter().all(|c| c.is_ascii_graphic() || *c == b' ')
+}
+
+/// Positions where the compressed header may start, the likely one first. AoK has no position of the next chapter,
+/// its header starts at 4. The position is after the end of truncated files, so the other start is tried too. A
+/// wrong start fails in the first few bytes
+pub(crate) fn header_starts(src: &[u8]) -> [usize; 2] {
+    match peek_u32_at(src, 4) {
+        Some(nextpos) if (nextpos as usize) < src.len() => [8, 4],
+        _ => [4, 8],
+    }
+}
+
+/// Body starts after the header length stored in the file, or after the compressed header if that is further. The
+/// stored length is missing(0) in some files and too small in others
+pub(crate) fn body_start(rawheader_end: usize, rawheader_begin: usize, compressed_len: usize) -> usize {
+    rawheader_end.max(rawheader_begin + compressed_len)
+}
+
+/// Check a count read from the file against its limit
+fn check_limit(limit: &'static str, value: usize, max: usize) -> Result<()> {
+    if value > max {
+        return Err(Error::LimitExceeded { section: Section::Unknown, offset: 0, limit, max });
+    }
+    Ok(())
+}
+
+/// Set `field` to `value` read in the layout shared with AoC, unless the lobby block of HD 5.x has set it already
+fn set_unless_lobby<T>(field: &mut Option<T>, value: Option<T>, lobby: bool) {
+    if !lobby {
+        *field = value;
+    }
+}
+
+/// Check positions in the chapter header at `headerpos`: body of the chapter starts at `start`, the next chapter at
+/// `next_chapter_pos`, 0 if there is none. Returns the end of the chapter, `usize::MAX` for the last one
+pub(crate) fn chapter_end(headerpos: usize, start: usize, next_chapter_pos: u32) -> Result<usize> {
+    if start < headerpos {
+        return Err(Error::OutOfBounds {
+            section: Section::Body,
+            offset: headerpos,
+            reason: format!("Chapter @ {} starts before its header", start),
+        });
+    }
+    match next_chapter_pos as usize {
+        0 => Ok(usize::MAX),
+        // Chapters never go backwards, or the loop would never end
+        end if end <= headerpos => Err(Error::OutOfBounds {
+            section: Section::Body,
+            offset: headerpos + 4,
+            reason: format!("Next chapter @ {} is before current chapter", next_chapter_pos),
+        }),
+        end if end < start => Err(Error::OutOfBounds {
+            section: Section::Body,
+            offset: headerpos,
+            reason: format!("Chapter @ {} starts after the next chapter", start),
+        }),
+        end => Ok(end),
+    }
+}
+
+/// Read a u32 at `pos` of the file, `None` if it's out of the file
+pub(crate) fn peek_u32_at(src: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(src.get(pos..pos.checked_add(4)?)?.try_into().ok()?))
+}
+
+/// Log version or the field after it should be 500
+fn check_body_meta<T: AsRef<[u8]>>(b: &StreamCursor<T>) -> Result<()> {
+    if b.peek_i32() != Some(500) {
+        return Err(Error::OutOfBounds {
+            section: Section::Body,
+            offset: b.filepos(b.tell()),
+            reason: format!("Unexpected body meta {:?}", b.peek_i32()),
+        });
+    }
+    Ok(())
+}
+
+/// Options of parsing. Both debug and release builds behave the same with the same options
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Fail on unexpected data instead of skipping it, e.g. unknown operations or unusual sync data in body
+    /// and chapter headers that can't be decompressed. Lenient parsing reports most of skipped data in `Record.warnings`
+    pub strict: bool,
+    /// When lenient parsing meets garbage in body, scan forward for the next plausible operation and resume from there.
+    /// Skipped ranges are reported as `Warning::SkippedBody`. Otherwise garbage is read as misaligned operations
+    pub recover: bool,
+    /// Stop after header: settings, players, teams, map, lobby chat and scenario. Body is not parsed, so `duration`,
+    /// `durationafterrestore`, `savetimes`, `chapters`, `issavedgame`, in-game chat, resign and age times of players
+    /// and winners are missing. So are `ismultiplayer` and `recorder` of DE, which are read from body meta. `guid` is
+    /// `None`, early commands in body are part of it
+    pub header_only: bool,
+    /// Keep decompressed headers of chapters after the first one in `Chapter.header`, several MB each. Otherwise
+    /// only positions of chapters are kept, and chapter headers are only decompressed to be checked in strict mode
+    pub chapter_headers: bool,
+    pub limits: Limits,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strict: false,
+            recover: true,
+            header_only: false,
+            chapter_headers: false,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Limits of resources used by parsing, for files from untrusted sources. Exceeding any of them fails parsing with
+/// `Error::LimitExceeded`. Defaults are far above what real recorded games need
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Decompressed size of the header or a chapter header in bytes
+    pub header: usize,
+    pub ai_strings: usize,
+    pub triggers: usize,
+    /// Effects of each trigger
+    pub effects: usize,
+    pub particles: usize,
+    /// Operations in body, all chapters counted
+    pub body_ops: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            header: 64 << 20,
+            ai_strings: 10000,
+            triggers: 10000,
+            effects: 10000,
+            particles: 1000000,
+            body_ops: 50000000,
+        }
+    }
+}
 
 /// Recorded game parser. Used to parse recorded game file
 pub struct Parser<T: AsRef<[u8]>> {
     pub header: StreamCursor<Vec<u8>>,
     pub body: StreamCursor<T>,
     pub md5: String,
+    pub options: ParseOptions,
+    /// The file ends in the compressed header, only the part before the cut is in `header`
+    pub truncated: bool,
 }
 
 impl<T: AsRef<[u8]>> Parser<T> {
-    /// Input buffer will be consumed
+    /// Input buffer will be consumed. Parsing is lenient, see `ParseOptions`
     pub fn new(src: T) -> Result<Self> {
+        Self::with_options(src, ParseOptions::default())
+    }
+
+    pub fn with_options(src: T, options: ParseOptions) -> Result<Self> {
         let md5 = md5::hash(&src).to_hex_lowercase();
 
         let b = src.as_ref();
-        // Sometimes header length is missing(always 0x00), calculate actual header length with decompressed length is more reliable
-        let mut rawheader_end = u32::from_le_bytes(b[0..4].try_into()?);
-        let nextpos = u32::from_le_bytes(b[4..8].try_into()?);
-        let rawheader_begin = if nextpos < b.len() as u32 { 8 } else { 4 };
-
-        let mut header_buffer = Vec::new();
-        let mut decoder = ZlibDecoder::new_with_decompress(&b[rawheader_begin as usize..], Decompress::new(false));
-        decoder.read_to_end(&mut header_buffer)?;
-        let compressed_size = decoder.total_in(); // Get compressed size
-
-        if rawheader_end < compressed_size as u32 + rawheader_begin {
-            rawheader_end = compressed_size as u32 + rawheader_begin;
-        }
-
-        #[cfg(debug_assertions)]
-        if rawheader_begin == 8 && nextpos > 0 {
-            let mut chapter_buffer = Vec::new();
-            match ZlibDecoder::new_with_decompress(&b[nextpos as usize + 8..], Decompress::new(false))
-                .read_to_end(&mut chapter_buffer)
-            {
-                Err(_) => bail!("Failed to decompress chapter header"),
-                _ => {
-                    // println!("Decompressed chapter header size: {}", chapter_buffer.len());
-                    // write test body to file
-                    // std::fs::write("testbody.bin", &test_buffer).unwrap();
-                }
-            }
+        if b.len() < 8 {
+            return Err(Error::OutOfBounds { section: Section::File, offset: 0, reason: "File is too small".into() });
         }
+        let rawheader_end = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+        let [first_begin, other_begin] = header_starts(b);
+        let mut rawheader_begin = first_begin;
+        let inflated = match inflate_header(&b[first_begin..], options.limits.header) {
+            Err(Error::Decompression { .. }) => {
+                rawheader_begin = other_begin;
+                inflate_header(&b[other_begin..], options.limits.header)
+            }
+            x => x,
+        };
+        let (header_buffer, compressed_size, complete) =
+            inflated.map_err(|e| e.locate(Section::File, rawheader_begin))?;
+
+        // Saved games(.msx) have no body
+        let body_start = body_start(rawheader_end, rawheader_begin, compressed_size).min(b.len());
 
         let header = StreamCursor::new(header_buffer, 0); // header cursor
-        let body = StreamCursor::new(src, rawheader_end as usize); // body cursor
+        let body = StreamCursor::new(src, body_start); // body cursor
 
-        Ok(Parser { header, body, md5 })
+        Ok(Parser { header, body, md5, options, truncated: !complete })
     }
 
     pub fn dump_header(&self, filename: &str) -> Result<()> {
@@ -71,67 +258,112 @@ impl<T: AsRef<[u8]>> Parser<T> {
     /// Try to extract info from the recorded game.   
     /// Parsing may not be complete. Check `None` for fields when using `Record`.
     pub fn parse_to(&mut self, r: &mut Record) -> Result<&mut Self> {
+        self.parse_with(r, &mut ())
+    }
+
+    /// Same as `parse_to()`, calling `visitor` for operations in body in the same pass
+    pub fn parse_with(&mut self, r: &mut Record, visitor: &mut dyn BodyVisitor) -> Result<&mut Self> {
+        r.debug.section = Section::Version;
+        r.truncated = self.truncated;
+        match self.parse_sections(r, visitor) {
+            Ok(()) => Ok(self),
+            // Keep what is parsed from a truncated header, sections after the cut are missing
+            Err(e) if self.truncated && e.is_cut() && r.debug.section != Section::Body => Ok(self),
+            Err(e) => {
+                let offset = match r.debug.section {
+                    Section::Body => self.body.offset + self.body.tell(),
+                    _ => self.header.tell(),
+                };
+                Err(e.locate(r.debug.section, offset))
+            }
+        }
+    }
+
+    /// Iterate over operations in body without filling a `Record`, following chapters the same way as `parse_to()`.
+    /// Header is parsed again for the version and the time of the restore point. Garbage in body is skipped when
+    /// `ParseOptions.recover` is on, otherwise the iterator ends with an error. A `Parser` from `from_reader()` has
+    /// no body to iterate
+    pub fn events(&mut self) -> Result<Events<'_>> {
+        // Header of a truncated file is still checked, it may have an unsupported version
+        if self.body.src.as_ref().is_empty() || (self.body.data().is_empty() && !self.truncated) {
+            return Ok(Events::new(&[], VersionProfile::of(None, None), &self.options, 0, 0, 0));
+        }
+        let mut r = Record::default();
+        r.debug.section = Section::Version;
+        match self.parse_header(&mut r) {
+            Ok(()) => {}
+            // Nothing follows a truncated header
+            Err(e) if self.truncated && e.is_cut() => {
+                return Ok(Events::new(&[], VersionProfile::of(None, None), &self.options, 0, 0, 0))
+            }
+            Err(e) => return Err(e.locate(r.debug.section, self.header.tell())),
+        }
+
+        let src = self.body.src.as_ref();
+        let mut b = StreamCursor::new(src, self.body.offset);
+        let profile = r.profile();
+        let time = if profile.restore_time { val!(r.restoretime) } else { 0 };
+        if b.remain() > 0 {
+            parse_body_meta(&mut b, &mut r, &self.options).map_err(|e| e.locate(Section::Body, b.filepos(b.tell())))?;
+        }
+        let nextpos = peek_u32_at(src, 4).unwrap_or_default();
+        let events = Events::new(src, profile, &self.options, b.filepos(b.tell()), nextpos, time);
+        events.check_first_op()?;
+        Ok(events)
+    }
+
+    /// Sections are parsed in order, `r.debug.section` tells where an error occurs
+    fn parse_sections(&mut self, r: &mut Record, visitor: &mut dyn BodyVisitor) -> Result<()> {
+        self.parse_header(r)?;
+        if self.options.header_only {
+            return Ok(());
+        }
+        self.parse_body_sections(r, visitor)
+    }
+
+    /// Parse header sections. The first u32 of body is needed to detect the version
+    pub(crate) fn parse_header(&mut self, r: &mut Record) -> Result<()> {
         r.md5 = Some(self.md5.clone());
+        self.header.seek(0);
+        self.body.seek(0);
 
         let h = &mut self.header;
 
-        let verraw: [u8; 7] = h.current()[0..7].try_into()?;
+        let verraw: [u8; 7] = val!(h.current().get(0..7).and_then(|x| x.try_into().ok()));
         r.verraw = Some(String::from_utf8_lossy(&verraw).to_string());
         h.mov(8);
         r.versave = h.get_f32();
         if -1.0 == val!(r.versave) {
             r.versave2 = h.get_u32();
         }
-        let verlog_check = self.body.peek_u32();
-        if verlog_check == Some(500) {
-            r.ver = Some(Version::AoK)
-        } else {
-            r.verlog = verlog_check;
-        }
-        match &verraw {
-            b"TRL 9.3" => {
-                if r.ver == Some(Version::AoK) {
-                    r.ver = Some(Version::AoKTrial);
-                } else {
-                    r.ver = Some(Version::AoCTrial);
-                }
-            }
-            b"VER 9.3" => r.ver = Some(Version::AoK),
-            b"VER 9.4" => {
-                if val!(r.verlog) == 0 || val!(r.verlog) == 3 {
-                    r.ver = Some(Version::AoC10a);
-                } else if val!(r.verlog) == 5 || val!(r.versave) >= 12.9699 {
-                    r.ver = Some(Version::DE);
-                } else if val!(r.versave) > 11.7601 {
-                    r.ver = Some(Version::HD);
-                } else if val!(r.verlog) == 4 {
-                    r.ver = Some(Version::AoC10c);
-                } else {
-                    r.ver = Some(Version::AoC);
-                }
-            }
-            b"VER 9.5" => r.ver = Some(Version::AoFE21),
-            b"VER 9.8" => r.ver = Some(Version::UP12),
-            b"VER 9.9" => r.ver = Some(Version::UP13),
-            b"VER 9.A" => r.ver = Some(Version::UP14RC1),
-            b"VER 9.B" => r.ver = Some(Version::UP14RC2),
-            b"VER 9.C" | b"VER 9.D" => r.ver = Some(Version::UP14),
-            b"VER 9.E" | b"VER 9.F" => r.ver = Some(Version::UP15),
-            b"MCP 9.F" => r.ver = Some(Version::MCP),
-            _ => r.ver = Some(Version::Unknown),
+        let (ver, verlog) = detect_version(&verraw, val!(r.versave), self.body.peek_u32());
+        r.ver = Some(ver);
+        r.verlog = verlog;
+        r.detect_mod_by_version();
+        if let Some(reason) = unsupported(val!(r.ver.as_ref()), val!(r.versave), r.versave2) {
+            return Err(Error::UnsupportedVersion { section: Section::Version, offset: 0, reason });
         }
 
-        // This parser don't support de/hd versions
-        if val!(r.versave) >= 11.7601 || val!(r.versave) < 0.0 {
-            bail!("DE/HD or higher versions are not supported");
+        let profile = r.profile();
+        if profile.de_header {
+            self.parse_de_header(r)?;
+            detect_coops(r, false);
+            return Ok(());
+        }
+        if profile.hd_header {
+            self.parse_hd_header(r)?;
         }
+        let lobby = profile.hd_header;
+        let h = &mut self.header;
 
         // https://github.com/goto-bus-stop/recanalyst/blob/master/src/Analyzers/HeaderAnalyzer.php#L305
+        r.debug.section = Section::Ai;
         r.debug.aipos = h.tell();
         r.include_ai = h.get_bool(4);
         if val!(r.include_ai) {
             h.mov(2);
             let num_ai_strings = val!(h.get_u16());
+            check_limit("AI strings", num_ai_strings as usize, self.options.limits.ai_strings)?;
             h.mov(4);
             for _ in 0..num_ai_strings {
                 let str_len = val!(h.get_u32());
@@ -151,35 +383,47 @@ impl<T: AsRef<[u8]>> Parser<T> {
             }
             h.mov(104 + 320 + 1024);
             h.mov(4096);
+            h.mov(profile.ai_extra as isize);
         }
 
         // https://github.com/goto-bus-stop/recanalyst/blob/master/src/Analyzers/HeaderAnalyzer.php#L68
+        r.debug.section = Section::Replay;
         h.mov(12);
-        r.speed_raw = h.get_u32();
+        set_unless_lobby(&mut r.speed_raw, h.get_u32(), lobby);
         h.mov(29);
         r.recorder = h.get_u16();
         r.totalplayers = h.get_u8();
-        if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
+        if profile.cheats {
             r.instantbuild = h.get_bool(1);
-            r.enablecheats = h.get_bool(1);
+            set_unless_lobby(&mut r.enablecheats, h.get_bool(1), lobby);
         }
         h.mov(2 + 58);
 
         // https://github.com/goto-bus-stop/recanalyst/blob/master/src/Analyzers/MapDataAnalyzer.php#L7
+        r.debug.section = Section::Map;
         r.mapx = h.get_i32();
         r.mapy = h.get_i32();
-        if val!(r.mapx) < 0 || val!(r.mapy) < 0 {
-            bail!("Map size is negative");
+        let reason = if val!(r.mapx) < 0 || val!(r.mapy) < 0 {
+            Some("Map size is negative")
         } else if val!(r.mapx) > 10000 || val!(r.mapy) > 10000 {
-            bail!("Map size is too large");
+            Some("Map size is too large")
         } else if val!(r.mapx) != val!(r.mapy) {
-            bail!("Map is not square");
+            Some("Map is not square")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(Error::OutOfBounds { section: Section::Map, offset: h.tell() - 8, reason: reason.into() });
         }
 
         let num_mapzones = val!(h.get_i32());
         let map_bits: isize = (val!(r.mapx) * val!(r.mapy)) as isize;
         for _ in 0..num_mapzones as usize {
-            h.mov(1275 + map_bits);
+            if profile.wide_mapzones {
+                h.mov(2048 + map_bits * 2);
+            } else {
+                h.mov(1275 + map_bits);
+            }
             let num_floats = val!(h.get_i32());
             h.mov(num_floats as isize * 4 + 4);
         }
@@ -198,27 +442,43 @@ impl<T: AsRef<[u8]>> Parser<T> {
         }
         let visibility_mapsize0 = val!(h.get_i32()) as isize;
         let visibility_mapsize1 = val!(h.get_i32()) as isize;
-        h.mov(visibility_mapsize0 * visibility_mapsize1 * 4);
+        h.mov(visibility_mapsize0.saturating_mul(visibility_mapsize1).saturating_mul(4));
         r.restoretime = h.get_u32();
+        r.isrestored = r.restoretime.map(|t| t > 0);
         let num_particles = val!(h.get_u32());
+        check_limit("particles", num_particles as usize, self.options.limits.particles)?;
         h.mov(27 * num_particles as isize + 4);
 
-        r.debug.initpos = h.tell();
-        if r.ver == Some(Version::AoKTrial) {
-            r.debug.initpos += 4;
+        r.debug.initpos = h.tell() + profile.init_skip;
+
+        // Locate player init data, scenario, victory, disabled techs, game settings and trigger by walking header.
+        // Search for them if the walk fails
+        r.debug.section = Section::Scenario;
+        let mut walked_players = None;
+        match walk_sections(h, profile, r.debug.initpos, val!(r.totalplayers) as usize) {
+            Some(sections) => {
+                walked_players = Some(sections.players);
+                r.debug.scenariopos = sections.scenario;
+                r.debug.victorypos = sections.victory;
+                r.debug.disabledtechspos = sections.disabledtechs;
+                r.debug.settingspos = sections.settings;
+                r.debug.triggerpos = sections.trigger;
+                if sections.scenariosearched {
+                    r.warnings.push(Warning::ScenarioSearched);
+                }
+            }
+            None => {
+                r.warnings.push(Warning::HeaderSearched);
+                search_sections(h, r, profile)?;
+            }
         }
 
-        // Find trigger
-        let needle = vec![0x9a, 0x99, 0x99, 0x99, 0x99, 0x99, 0xf9, 0x3f];
-        match h.rfind(&needle, 0..h.data().len()) {
-            Some(pos) => r.debug.triggerpos = pos + needle.len(),
-            None => bail!("can't find triggerpos"),
-        };
-
         // Skip trigger
+        r.debug.section = Section::Trigger;
         h.seek(r.debug.triggerpos);
         h.mov(1);
         let num_triggers = val!(h.get_i32());
+        check_limit("triggers", num_triggers.max(0) as usize, self.options.limits.triggers)?;
         for _ in 0..num_triggers {
             h.mov(18); // 4 + (2 * 1) + (3 * 4)
             let description_len = val!(h.get_i32());
@@ -230,6 +490,7 @@ impl<T: AsRef<[u8]>> Parser<T> {
                 h.mov(name_len as isize);
             }
             let num_effects = val!(h.get_i32());
+            check_limit("effects", num_effects.max(0) as usize, self.options.limits.effects)?;
             for _ in 0..num_effects {
                 h.mov(24);
                 let mut num_selected_objs = val!(h.get_i32());
@@ -248,33 +509,32 @@ impl<T: AsRef<[u8]>> Parser<T> {
                 h.mov(4 * num_selected_objs as isize);
             }
             h.mov(4 * num_effects as isize);
-            let num_conditions = val!(h.get_i32());
+            let num_conditions = val!(h.get_i32()) as isize;
             let condition_size = 72;
-            h.mov((num_conditions * condition_size + num_conditions * 4) as isize);
+            h.mov(num_conditions * condition_size + num_conditions * 4);
         }
         if num_triggers > 0 {
             h.mov(4 * num_triggers as isize);
         }
 
         // Lobby
+        r.debug.section = Section::Lobby;
         for i in 1..9 {
             r.players[i].teamid = h.get_u8();
         }
         h.mov(1);
-        r.revealmap_raw = h.get_i32();
+        set_unless_lobby(&mut r.revealmap_raw, h.get_i32(), lobby);
         h.mov(4); // fog of war
         r.mapsize_raw = h.get_u32();
-        r.poplimit = h.get_u32();
-        if val!(r.poplimit) <= 40 {
-            r.poplimit = r.poplimit.map(|pop| pop * 25);
-        }
-        if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
-            r.gametype_raw = h.get_u8();
+        let poplimit = val!(h.get_u32());
+        set_unless_lobby(&mut r.poplimit, Some(if poplimit <= 40 { poplimit * 25 } else { poplimit }), lobby);
+        if profile.lobby {
+            set_unless_lobby(&mut r.gametype_raw, h.get_u8(), lobby);
             r.lockdiplomacy = h.get_bool(1);
 
             let totalchats = val!(h.get_i32());
             for _ in 0..totalchats {
-                if h.peek_i32() == Some(0) {
+                if val!(h.peek_i32()) == 0 {
                     h.mov(4);
                     continue;
                 }
@@ -282,20 +542,13 @@ impl<T: AsRef<[u8]>> Parser<T> {
             }
         }
 
-        // Find game settings
-        let needle = vec![0x9d, 0xff, 0xff, 0xff];
-        match h.rfind(&needle, 0..r.debug.triggerpos) {
-            Some(pos) => r.debug.settingspos = pos,
-            None => bail!("can't find settingspos"),
-        };
-
-        // Locate disabled techs
-        r.debug.disabledtechspos = r.debug.settingspos - 5456;
-
-        // Locate victory pos
-        r.debug.victorypos = r.debug.disabledtechspos - 12544 - 44;
+        // Scenario options right before game settings: all techs(4), starting ages of 16 players(4 * 16)
+        r.debug.section = Section::Settings;
+        h.seek(r.debug.settingspos.saturating_sub(4 * 16 + 4));
+        r.alltechs = h.get_bool(4);
 
         // Victory
+        r.debug.section = Section::Victory;
         h.seek(r.debug.victorypos);
         h.mov(4);
         r.isconquest = h.get_bool(4);
@@ -305,96 +558,98 @@ impl<T: AsRef<[u8]>> Parser<T> {
         r.explored2win = h.get_i32();
         h.mov(4);
         r.anyorall = h.get_bool(4);
-        r.victorytype_raw = h.get_i32();
+        set_unless_lobby(&mut r.victorytype_raw, h.get_i32(), lobby);
         r.score2win = h.get_i32();
         r.time2win_raw = h.get_i32();
 
-        // Find scenario pos
-        let needle = match &r.ver {
-            Some(Version::AoK) | Some(Version::AoKTrial) => vec![0x9a, 0x99, 0x99, 0x3f], // float 1.20
-            _ => vec![0xf6, 0x28, 0x9c, 0x3f],                                            // float 1.22
-        };
-        match h.rfind(&needle, 0..r.debug.victorypos) {
-            Some(pos) => {
-                r.debug.scenariopos = pos - 4;
-            }
-            None => bail!("can't find scenariopos"),
-        };
+        // Scenario
+        r.debug.section = Section::Scenario;
         h.seek(r.debug.scenariopos);
         h.mov(4);
         r.verscenario = h.get_f32();
         h.mov(16 * 256 + 16 * 4 + 16 * 16 + 5 + 4);
         r.scenariofilename_raw = h.extract_str_l16();
-        h.mov(4 * 5);
-        if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
-            h.mov(4);
-        }
+        h.mov(4 * profile.string_ids as isize);
         r.instructions_raw = h.extract_str_l16();
 
         // Game settings
+        r.debug.section = Section::Settings;
         h.seek(r.debug.initpos);
         h.mov(2 + val!(r.totalplayers) as isize + 36 + 4 + 1);
         h.extract_str_l16();
-        let trail_types: [u8; 6] = h.current()[..6].try_into()?;
+        let trail_types: [u8; 6] = val!(h.current().get(..6).and_then(|x| x.try_into().ok()));
 
         h.seek(r.debug.settingspos);
 
         h.mov(4 + 8);
-        if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
-            r.mapid = h.get_u32();
+        if profile.mapid {
+            set_unless_lobby(&mut r.mapid, h.get_u32(), lobby);
         }
-        r.difficulty_raw = h.get_i32();
-        r.lockteams = h.get_bool(4);
+        set_unless_lobby(&mut r.difficulty_raw, h.get_i32(), lobby);
+        set_unless_lobby(&mut r.lockteams, h.get_bool(4), lobby);
         let mut init_search_needles = Vec::new();
         for i in 0..9 {
             r.players[i].index = h.get_i32();
             r.players[i].playertype = h.get_i32();
             let namelen = val!(h.peek_i32());
             let mut init_search_needle = Vec::new();
-            init_search_needle.extend_from_slice((namelen as i16 + 1).to_le_bytes().as_ref());
-            init_search_needle.extend_from_slice(&h.current()[4..4 + namelen as usize]);
+            init_search_needle.extend_from_slice((namelen as i16).wrapping_add(1).to_le_bytes().as_ref());
+            init_search_needle.extend_from_slice(val!(h.current().get(4..).and_then(|x| x.get(..namelen as usize))));
             init_search_needle.push(b'\0');
             init_search_needle.extend_from_slice(trail_types.as_ref());
             init_search_needles.push(init_search_needle);
             r.players[i].name_raw = h.extract_str_l32();
         }
 
-        // Find data pos in init
-        h.seek(r.debug.initpos + 2 + val!(r.totalplayers) as usize + 36 + 4 + 1);
-        let mut easy_skip_start = h.tell() + 35100 + val!(r.mapx) as usize * val!(r.mapy) as usize;
-        let search_end_pos = if r.debug.scenariopos != 0 {
-            r.debug.scenariopos
-        } else if r.debug.victorypos != 0 {
-            r.debug.victorypos
-        } else if r.debug.disabledtechspos != 0 {
-            r.debug.disabledtechspos
-        } else if r.debug.settingspos != 0 {
-            r.debug.settingspos
+        // Data pos in init, searched for by player names if the walk failed
+        r.debug.section = Section::Init;
+        if let Some(players) = walked_players {
+            for i in 1..9 {
+                let Some(idx) = r.players[i].index.filter(|idx| (0..=8).contains(idx)) else {
+                    continue;
+                };
+                if !r.players[i].isvalid() {
+                    continue;
+                }
+                match players[idx as usize] {
+                    Some(pos) => r.debug.playerinitpos_by_idx[idx as usize] = Some(pos),
+                    None => r.warnings.push(Warning::PlayerInitNotFound { slot: i }),
+                }
+            }
         } else {
-            h.data().len()
-        } - val!(r.totalplayers) as usize * 1817;
-
-        for i in 1..9 {
-            if !r.players[i].isvalid()
-                || r.players[i].index.is_none()
-                || r.players[i]
-                    .index
-                    .is_some_and(|idx| !(0..=8).contains(&idx) || r.debug.playerinitpos_by_idx[idx as usize].is_some())
-            {
-                continue;
+            h.seek(r.debug.initpos + 2 + val!(r.totalplayers) as usize + 36 + 4 + 1);
+            let mut easy_skip_start = h.tell() + 35100 + val!(r.mapx) as usize * val!(r.mapy) as usize;
+            let search_end_pos = if r.debug.scenariopos != 0 {
+                r.debug.scenariopos
+            } else if r.debug.victorypos != 0 {
+                r.debug.victorypos
+            } else if r.debug.disabledtechspos != 0 {
+                r.debug.disabledtechspos
+            } else if r.debug.settingspos != 0 {
+                r.debug.settingspos
+            } else {
+                h.data().len()
             }
+            .saturating_sub(val!(r.totalplayers) as usize * 1817);
+
+            for i in 1..9 {
+                if !r.players[i].isvalid()
+                    || r.players[i].index.is_none()
+                    || r.players[i].index.is_some_and(|idx| {
+                        !(0..=8).contains(&idx) || r.debug.playerinitpos_by_idx[idx as usize].is_some()
+                    })
+                {
+                    continue;
+                }
 
-            if let Some(needle) = init_search_needles.get(i).cloned() {
-                let pos = h.find(needle, easy_skip_start..search_end_pos);
-                if pos.is_some() {
-                    r.debug.playerinitpos_by_idx[val!(r.players[i].index) as usize] = pos;
-                    h.seek(val!(pos));
-                    easy_skip_start = h.tell();
-                } else {
-                    // print a hint only in debug mode
-                    #[cfg(debug_assertions)]
-                    {
-                        println!("Player {} not found, needle: {:02x?}", i, init_search_needles.get(i).cloned());
+                if let Some(needle) = init_search_needles.get(i).cloned() {
+                    let pos = h.find(needle, easy_skip_start..search_end_pos);
+                    if pos.is_some() {
+                        *val!(r.debug.playerinitpos_by_idx.get_mut(val!(r.players[i].index) as usize)) = pos;
+                        h.seek(val!(pos));
+                        easy_skip_start = h.tell();
+                    } else {
+                        r.warnings.push(Warning::PlayerInitNotFound { slot: i });
                     }
                 }
             }
@@ -403,19 +658,20 @@ impl<T: AsRef<[u8]>> Parser<T> {
         // Analyze diplomacy
         let mut playerpos = r.debug.playerinitpos_by_idx;
         let totalplayers = val!(r.totalplayers) as usize;
+        let num_indexes = totalplayers.min(playerpos.len());
         for i in 1..9 {
             if r.players[i].index.is_none() {
                 continue;
             }
             // println!("Current playerpos(loop i: {}): {:?}", i, playerpos);
             let idx = val!(r.players[i].index) as usize;
-            if playerpos[idx].is_some() && r.players[i].isvalid() {
+            if playerpos.get(idx).is_some_and(|pos| pos.is_some()) && r.players[i].isvalid() {
                 let mut team_members = vec![idx as i32];
-                let pos_my_diplomacy = val!(playerpos[idx]) - (5 + 36);
-                let pos_diplomacy = pos_my_diplomacy - totalplayers; // first one is GAIA
+                let pos_my_diplomacy = val!(val!(playerpos[idx]).checked_sub(5 + 36));
+                let pos_diplomacy = val!(pos_my_diplomacy.checked_sub(totalplayers)); // first one is GAIA
                 // println!("  my slot: {}, my idx: {}, totalplayers: {}", i, idx, totalplayers);
                 #[allow(clippy::needless_range_loop)]
-                for j in (idx + 1)..totalplayers {
+                for j in (idx + 1)..num_indexes {
                     // print!("   checking with idx: {}", j);
                     if playerpos[j].is_none() {
                         // println!("    already processed, skip");
@@ -442,7 +698,7 @@ impl<T: AsRef<[u8]>> Parser<T> {
 
         // Init data
         for i in 0..9 {
-            let pos_by_idx = r.debug.playerinitpos_by_idx[val!(r.players[i].index) as usize];
+            let pos_by_idx = r.debug.playerinitpos_by_idx.get(val!(r.players[i].index) as usize).copied().flatten();
             // Which is put before '&&' makes a difference
             if r.players[i].isvalid() && pos_by_idx.is_some() {
                 h.seek(val!(pos_by_idx));
@@ -465,19 +721,22 @@ impl<T: AsRef<[u8]>> Parser<T> {
                 r.players[i].initcivilian = h.get_f32();
                 h.mov(4 * 2);
                 r.players[i].initmilitary = h.get_f32();
-                h.mov(756 - 41 * 4);
-                if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
-                    h.mov(36);
-                }
-                if r.ver == Some(Version::UP15) || r.ver == Some(Version::MCP) {
-                    r.players[i].modversion = h.get_f32();
+                h.mov(756 - 41 * 4 + profile.player_extra as isize);
+                if profile.datamod {
+                    // Resource 198
+                    let raw = val!(h.get_f32());
+                    r.detect_datamod(raw);
+                    #[allow(deprecated)]
+                    {
+                        r.players[i].modversion = Some(raw);
+                    }
                     h.mov(4 * 6 + 4 * 7 + 4 * 28);
                 }
                 h.mov(1);
                 r.players[i].initx = h.get_f32();
                 r.players[i].inity = h.get_f32();
 
-                if r.ver != Some(Version::AoK) && r.ver != Some(Version::AoKTrial) {
+                if profile.saved_views {
                     let num_savedviews = val!(h.get_i32());
                     if num_savedviews > 0 {
                         h.mov(num_savedviews as isize * 8);
@@ -491,48 +750,200 @@ impl<T: AsRef<[u8]>> Parser<T> {
             }
         }
 
-        // Body
-        let b = &mut self.body;
+        // Without spectator slots, co-op partners are unknown until body commands are counted
+        detect_coops(r, false);
 
-        if r.ver == Some(Version::AoK) || r.ver == Some(Version::AoKTrial) {
-            debug_assert!(val!(b.peek_i32()) == 500);
-            b.mov(36);
-        } else {
-            b.mov(4);
-            debug_assert!(val!(b.peek_i32()) == 500);
-            b.mov(4); // interval
-            r.ismultiplayer = b.get_bool(4);
-            b.mov(16);
-        }
+        // Lobby settings not stored in header
+        r.apply_instructions();
 
-        let mut next_chapter_pos = u32::from_le_bytes(b.src.as_ref()[4..8].try_into()?);
-        if r.ver == Some(Version::AoK) || r.ver == Some(Version::AoKTrial) || next_chapter_pos == 0 {
-            parse_body(b, r)?;
+        Ok(())
+    }
+
+    /// Parse body by chapters, then fill fields depending on the whole game
+    fn parse_body_sections(&mut self, r: &mut Record, visitor: &mut dyn BodyVisitor) -> Result<()> {
+        r.debug.section = Section::Body;
+        let b = &mut self.body;
+        let profile = r.profile();
+        if profile.restore_time {
+            // Time in body starts from the restore point
+            r.duration = val!(r.restoretime);
+            if b.remain() == 0 {
+                r.issavedgame = (!self.truncated).then_some(true);
+                r.guid = Some(calc_guid(r)?);
+                return Ok(());
+            }
+        }
+        r.issavedgame = Some(false);
+        parse_body_meta(b, r, &self.options)?;
+
+        let mut next_chapter_pos = if profile.chapters { val!(peek_u32_at(b.src.as_ref(), 4)) } else { 0 };
+        if next_chapter_pos == 0 {
+            r.chapters.push(Chapter {
+                headerpos: 0,
+                bodystart: b.offset + b.pos_in_data,
+                bodyend: b.src.as_ref().len(),
+                time: r.duration,
+                fromsave: false,
+                header: None,
+            });
+            parse_body(b, r, &self.options, visitor)?;
         } else {
+            let mut headerpos = 0;
+            let mut header = None;
             let mut start = b.pos_in_data + b.offset;
-            let mut end = next_chapter_pos as usize;
+            let mut end = (next_chapter_pos as usize).min(b.src.as_ref().len());
             loop {
-                let body_slice = b.src.as_ref()[start..end].to_vec();
-                let mut slice_stream = StreamCursor::new(body_slice, 0);
-                parse_body(&mut slice_stream, r)?;
+                // A chapter comes from a save command if there is one in the previous chapter
+                let fromsave = r.debug.savecmdfound;
+                r.debug.savecmdfound = false;
+                r.chapters.push(Chapter {
+                    headerpos,
+                    bodystart: start,
+                    bodyend: end,
+                    time: r.duration,
+                    fromsave,
+                    header,
+                });
+
+                let mut slice_stream = StreamCursor::new(&b.src.as_ref()[..end], start);
+                parse_body(&mut slice_stream, r, &self.options, visitor)?;
 
                 if end >= b.src.as_ref().len() {
                     break;
                 }
 
-                start = u32::from_le_bytes(b.src.as_ref()[end..end + 4].try_into()?) as usize;
-                next_chapter_pos = u32::from_le_bytes(b.src.as_ref()[end + 4..end + 8].try_into()?);
-                if next_chapter_pos == 0 {
-                    end = b.src.as_ref().len();
-                } else {
-                    end = next_chapter_pos as usize;
+                headerpos = end;
+                header = None;
+                if self.options.chapter_headers || self.options.strict {
+                    let inflated = b.src.as_ref().get(end + 8..).map(|x| inflate_header(x, self.options.limits.header));
+                    header = match inflated {
+                        Some(Ok((x, _, _))) => Some(x).filter(|_| self.options.chapter_headers),
+                        Some(Err(e)) if self.options.strict || matches!(e, Error::LimitExceeded { .. }) => {
+                            return Err(e.locate(Section::Body, end + 8))
+                        }
+                        _ => None,
+                    };
                 }
+                start = val!(peek_u32_at(b.src.as_ref(), end)) as usize;
+                next_chapter_pos = val!(peek_u32_at(b.src.as_ref(), end + 4));
+                end = chapter_end(headerpos, start, next_chapter_pos)?.min(b.src.as_ref().len());
+                // A cut file may end before the chapter starts
+                start = start.min(end);
             }
         }
 
-        r.guid = Some(calc_guid(r)?);
-        guess(r)?;
+        finish(r)
+    }
+}
+
+/// A co-op partner issues at least 1/`COOP_SHARE` of the unit commands of the busiest slot. In test records,
+/// watchers joined as partners issue 0 to 5% (66 of 1298), co-op partners issue as many as their main operator
+const COOP_SHARE: usize = 10;
+
+/// Group co-op players and list spectators. Without spectator slots, watchers join a player as a co-op partner and
+/// are told from co-op players by unit commands in body, see `COOP_SHARE`. Partners are unknown until `body_parsed`
+fn detect_coops(r: &mut Record, body_parsed: bool) {
+    r.coops.clear();
+    r.spectators.clear();
+    r.unknownpartners.clear();
+    let busiest = r.debug.unitcmds.iter().max().copied().unwrap_or(0);
+    let has_spectator_slots = r.profile().spectator_slots || r.players.iter().any(|p| p.isspectator());
+    for i in 1..9 {
+        if r.players[i].isspectator() {
+            r.spectators.push(i);
+            continue;
+        }
+        if !r.players[i].isvalid() || r.players[i].ismainop != Some(true) {
+            continue;
+        }
+        let mut coop = vec![i];
+        for j in 1..9 {
+            let partner = &r.players[j];
+            if !partner.isvalid() || partner.ismainop != Some(false) || partner.index != r.players[i].index {
+                continue;
+            }
+            let unitcmds = r.debug.unitcmds[j];
+            if has_spectator_slots || (unitcmds > 0 && unitcmds * COOP_SHARE >= busiest) {
+                coop.push(j);
+            } else if body_parsed {
+                r.spectators.push(j);
+            } else {
+                r.unknownpartners.push(j);
+            }
+        }
+        if coop.len() > 1 {
+            r.coops.push(coop);
+        }
+    }
+    r.spectators.sort();
+}
+
+/// Locate sections the old way when walking header fails: the last trigger version in header, the last separator
+/// before triggers as game settings, then fixed offsets back to victory and a backward search for the scenario
+/// version. Offset of a missing section is where the backward search starts
+fn search_sections(h: &StreamCursor<Vec<u8>>, r: &mut Record, profile: &VersionProfile) -> Result<()> {
+    r.debug.section = Section::Trigger;
+    match h.rfind(&TRIGGER_VERSION.to_vec(), 0..h.data().len()) {
+        Some(pos) => r.debug.triggerpos = pos + TRIGGER_VERSION.len(),
+        None => return Err(Error::SectionNotFound { section: Section::Trigger, offset: h.data().len() }),
+    };
+
+    r.debug.section = Section::Settings;
+    match h.rfind(&SEPARATOR.to_vec(), 0..r.debug.triggerpos) {
+        Some(pos) => r.debug.settingspos = pos,
+        None => return Err(Error::SectionNotFound { section: Section::Settings, offset: r.debug.triggerpos }),
+    };
+    r.debug.disabledtechspos = val!(r.debug.settingspos.checked_sub(DISABLEDTECHS_LEN));
+    r.debug.victorypos = val!(r.debug.disabledtechspos.checked_sub(12544 + 44));
+
+    r.debug.section = Section::Scenario;
+    match h.rfind(&profile.scenario_version.to_vec(), 0..r.debug.victorypos) {
+        Some(pos) if pos >= 4 => r.debug.scenariopos = pos - 4,
+        _ => return Err(Error::SectionNotFound { section: Section::Scenario, offset: r.debug.victorypos }),
+    };
+    Ok(())
+}
 
-        Ok(self)
+/// Skip body meta before operations. It starts with log version, 500 for AoK and the second u32 for others
+pub(crate) fn parse_body_meta<T: AsRef<[u8]>>(
+    b: &mut StreamCursor<T>,
+    r: &mut Record,
+    options: &ParseOptions,
+) -> Result<()> {
+    match r.profile().body_meta {
+        BodyMeta::DE => {
+            b.mov(4); // log version
+            b.mov(4); // interval
+            r.ismultiplayer = b.get_bool(4);
+            r.recorder = b.get_u32().map(|x| x as u16);
+            b.mov(16);
+        }
+        BodyMeta::AoK => {
+            if options.strict {
+                check_body_meta(b)?;
+            }
+            b.mov(36);
+        }
+        BodyMeta::AoC => {
+            b.mov(4);
+            if options.strict {
+                check_body_meta(b)?;
+            }
+            b.mov(4); // interval
+            r.ismultiplayer = b.get_bool(4);
+            b.mov(16);
+        }
     }
+    Ok(())
+}
+
+/// Fill fields depending on the whole game after body is parsed
+pub(crate) fn finish(r: &mut Record) -> Result<()> {
+    r.durationafterrestore = r.duration.saturating_sub(r.restoretime.unwrap_or(0));
+    detect_coops(r, true);
+
+    r.guid = Some(calc_guid(r)?);
+    guess(r)?;
+
+    Ok(())
 }
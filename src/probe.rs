@@ -0,0 +1,125 @@
+use crate::error::{Error, Result, Section};
+use crate::parser::{body_start, has_version, header_starts, Limits};
+use crate::profile::{unsupported, DE_SAVE, HD_SAVE};
+use crate::record::Version;
+use flate2::{Decompress, FlushDecompress, Status};
+use serde::Serialize;
+
+/// Version information of a recorded game. See `probe()`
+#[derive(Debug, Serialize)]
+pub struct Probe {
+    pub ver: Version,
+    pub verraw: String,
+    pub versave: f32,
+    pub versave2: Option<u32>,
+    pub verlog: Option<u32>,
+    /// Whether `Parser::parse_to()` supports this version
+    pub supported: bool,
+}
+
+/// Detect version from version string, save version and the first 4 bytes of body.
+/// Returns the version and log version. Log version of AoK is not returned
+pub(crate) fn detect_version(verraw: &[u8; 7], versave: f32, verlog_check: Option<u32>) -> (Version, Option<u32>) {
+    let (isaok, verlog) = if verlog_check == Some(500) { (true, None) } else { (false, verlog_check) };
+    let ver = match verraw {
+        b"TRL 9.3" => {
+            if isaok {
+                Version::AoKTrial
+            } else {
+                Version::AoCTrial
+            }
+        }
+        b"VER 9.3" => Version::AoK,
+        b"VER 9.4" => {
+            // Saved games(.msx) have no body to tell the log version
+            if verlog == Some(0) || verlog == Some(3) {
+                Version::AoC10a
+            } else if verlog == Some(5) || versave >= DE_SAVE {
+                Version::DE
+            } else if versave > HD_SAVE {
+                Version::HD
+            } else if verlog == Some(4) {
+                Version::AoC10c
+            } else {
+                Version::AoC
+            }
+        }
+        b"VER 9.5" => Version::AoFE21,
+        b"VER 9.8" => Version::UP12,
+        b"VER 9.9" => Version::UP13,
+        b"VER 9.A" => Version::UP14RC1,
+        b"VER 9.B" => Version::UP14RC2,
+        b"VER 9.C" | b"VER 9.D" => Version::UP14,
+        b"VER 9.E" | b"VER 9.F" => Version::UP15,
+        b"MCP 9.F" => Version::MCP,
+        _ => Version::Unknown,
+    };
+    (ver, verlog)
+}
+
+/// Get version of a recorded game without parsing it.
+/// Header is decompressed into a small buffer to find the body, it's not kept or parsed
+pub fn probe(src: &[u8]) -> Result<Probe> {
+    if src.len() < 8 {
+        return Err(Error::OutOfBounds { section: Section::File, offset: 0, reason: "File is too small".into() });
+    }
+    let rawheader_end = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as usize;
+    let [first_begin, other_begin] = header_starts(src);
+    let (rawheader_begin, header) = match version_info(&src[first_begin..]) {
+        Err(Error::Decompression { .. }) => (other_begin, version_info(&src[other_begin..])),
+        x => (first_begin, x),
+    };
+    let header = header.map_err(|e| e.locate(Section::File, rawheader_begin))?;
+
+    let verraw = [header[0], header[1], header[2], header[3], header[4], header[5], header[6]];
+    let versave = f32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    let versave2 =
+        if versave == -1.0 { Some(u32::from_le_bytes([header[12], header[13], header[14], header[15]])) } else { None };
+
+    // Header length is missing or too small sometimes, the whole header is decompressed to find the body as `Parser`
+    // does
+    let compressed_len = compressed_len(&src[rawheader_begin..], Limits::default().header)
+        .map_err(|e| e.locate(Section::File, rawheader_begin))?;
+    let body_begin = body_start(rawheader_end, rawheader_begin, compressed_len);
+    let verlog_check = src.get(body_begin..body_begin + 4).and_then(|x| x.try_into().ok()).map(u32::from_le_bytes);
+
+    let (ver, verlog) = detect_version(&verraw, versave, verlog_check);
+    let supported = unsupported(&ver, versave, versave2).is_none();
+    Ok(Probe { ver, verraw: String::from_utf8_lossy(&verraw).to_string(), versave, versave2, verlog, supported })
+}
+
+/// Decompress version string(8), save version(4) and new save version of DE(4) at the start of header
+fn version_info(src: &[u8]) -> Result<[u8; 16]> {
+    let mut header = [0u8; 16];
+    let mut decompress = Decompress::new(false);
+    let corrupt = |reason: String| Error::Decompression { section: Section::Unknown, offset: 0, reason };
+    decompress.decompress(src, &mut header, FlushDecompress::None).map_err(|e| corrupt(e.to_string()))?;
+    if !has_version(&header[..decompress.total_out() as usize]) {
+        return Err(corrupt("No version string in header".into()));
+    }
+    Ok(header)
+}
+
+/// Compressed length of the header, decompressed into a scratch buffer up to `limit` bytes
+fn compressed_len(src: &[u8], limit: usize) -> Result<usize> {
+    let mut decompress = Decompress::new(false);
+    let mut scratch = vec![0u8; 0x10000];
+    loop {
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress(&src[total_in as usize..], &mut scratch, FlushDecompress::None)
+            .map_err(|e| Error::Decompression { section: Section::Unknown, offset: 0, reason: e.to_string() })?;
+        if decompress.total_out() as usize > limit {
+            return Err(Error::LimitExceeded {
+                section: Section::Unknown,
+                offset: 0,
+                limit: "header size",
+                max: limit,
+            });
+        }
+        // A cut header ends where the input is used up
+        if status == Status::StreamEnd || (decompress.total_in() == total_in && decompress.total_out() == total_out) {
+            return Ok(decompress.total_in() as usize);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::record::{Record, Version};
+use std::ops::Range;
+
+/// Save versions after 11.76 of "VER 9.4" are HD
+pub(crate) const HD_SAVE: f32 = 11.7601;
+/// HD save versions from 11.93 on have map zones of u16 tiles
+const HD_WIDE_MAPZONES: f32 = 11.93;
+/// HD save versions from 12.35 on(HD 5.x) store lobby settings and players before AI data
+const HD_LOBBY: f32 = 12.35;
+/// Save versions from 12.97 on are DE
+pub(crate) const DE_SAVE: f32 = 12.9699;
+/// DE save versions read by `de_parser`, later ones changed lobby settings and players
+const DE_SAVES: Range<f32> = 63.0..64.0;
+
+/// Layout of body meta, the part of body before operations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BodyMeta {
+    /// 36 bytes, nothing known in it
+    AoK,
+    /// Log version, interval, multiplayer and 16 unknown bytes
+    AoC,
+    /// Log version, interval, multiplayer, recorder and 16 unknown bytes
+    DE,
+}
+
+/// What differs between versions: field sizes, optional blocks and magic values. Parsers read these instead of
+/// checking the version, a new version or mod needs a new profile in `VersionProfile::of()` only
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct VersionProfile {
+    /// Header after version info is parsed by `de_parser`, nothing of the header fields below applies
+    pub de_header: bool,
+    /// Lobby settings and players of HD 5.x right after version info, read by `hd_parser`. Header goes on as
+    /// other versions after it, settings it has are the only source of those fields
+    pub hd_header: bool,
+    /// Extra AI data after AI rules
+    pub ai_extra: usize,
+    /// Instant build and cheats flags in replay
+    pub cheats: bool,
+    /// Unknown bytes between particles and player init data
+    pub init_skip: usize,
+    /// Scenario version float, right after the first u32 of scenario header
+    pub scenario_version: [u8; 4],
+    /// String table ids of scenario messages
+    pub string_ids: usize,
+    /// Instructions, hints, victory, loss, history and scouts
+    pub messages: usize,
+    /// Map zones have 2048 bytes of info and u16 tiles, instead of 1275 bytes and u8 tiles
+    pub wide_mapzones: bool,
+    /// Map tiles are `DE_TILE_LEN` bytes, instead of 2 or 4 bytes told by the first tile
+    pub de_tiles: bool,
+    /// Map id in game settings
+    pub mapid: bool,
+    /// Unknown bytes in game settings after players, before a list of entries with float triples
+    pub settings_skip: usize,
+    /// Unknown bytes after that list, before the trigger version
+    pub trigger_skip: usize,
+    /// Game type, lock diplomacy and lobby chats after triggers
+    pub lobby: bool,
+    /// Selected group of static objects in player init data, before resources
+    pub static_group: usize,
+    /// Unknown bytes of static objects before the group id
+    pub static_skip: usize,
+    /// State of actions of units
+    pub action_state: usize,
+    /// Selected group and unknown bytes of action objects after the command flag
+    pub action_skip: usize,
+    /// Unknown bytes, selection effect and editor color of unit types before the outline
+    pub unit_type_skip: usize,
+    /// Base armor of unit types
+    pub base_armor: usize,
+    /// Bytes of combat objects after their own unit type
+    pub combat_skip: usize,
+    /// Unknown bytes at the end of unit AI
+    pub unit_ai_skip: usize,
+    /// Bytes of combat objects after unit AI
+    pub combat_tail: usize,
+    /// Bytes of buildings after the combat object
+    pub building_tail: usize,
+    /// Unknown bytes in player init data after initial military
+    pub player_extra: usize,
+    /// Data mod version and its data in player init data
+    pub datamod: bool,
+    /// Saved views in player init data
+    pub saved_views: bool,
+    /// Time of the restore point in header, game time in body starts from it
+    pub restore_time: bool,
+    pub body_meta: BodyMeta,
+    /// Body is split into chapters by saves, the position of the next chapter follows the header length
+    pub chapters: bool,
+    /// All operations in body are known, unknown ones are errors in strict mode
+    pub known_ops: bool,
+    /// Sync operations have checksums after a zero marker, instead of unknown data after a u32 that is not 3
+    pub sync_checksums: bool,
+    /// Postgame operation with achievements takes the rest of body
+    pub postgame: bool,
+    /// Length of chat operations with command 500 after the command
+    pub chat500_len: usize,
+    /// Chat messages in body are JSON
+    pub json_chat: bool,
+    /// In-game strings are UTF-8, their encoding is not detected from instructions
+    pub utf8_strings: bool,
+    /// Position of the player index in research commands. Tech id is at 10 for all versions
+    pub research_player: usize,
+    /// Position of the player index in build commands
+    pub build_player: usize,
+    /// Resign commands have the player index only, no slot and disconnected flag
+    pub resign_by_index: bool,
+    /// Map ids 59 and 60 are swapped, names are in `HD_MAP_NAMES_TRANS`
+    pub hd_map_names: bool,
+    /// Spectators are not in player slots, slots sharing a player index are all co-op players
+    pub spectator_slots: bool,
+}
+
+const AOC: VersionProfile = VersionProfile {
+    de_header: false,
+    hd_header: false,
+    ai_extra: 0,
+    cheats: true,
+    init_skip: 0,
+    scenario_version: [0xf6, 0x28, 0x9c, 0x3f], // float 1.22
+    string_ids: 6,
+    messages: 6,
+    wide_mapzones: false,
+    de_tiles: false,
+    mapid: true,
+    settings_skip: 1655,
+    trigger_skip: 125,
+    lobby: true,
+    static_group: 0,
+    static_skip: 0,
+    action_state: 1,
+    action_skip: 2,
+    unit_type_skip: 4,
+    base_armor: 2,
+    combat_skip: 23,
+    unit_ai_skip: 0,
+    combat_tail: 31,
+    building_tail: 127,
+    player_extra: 36,
+    datamod: false,
+    saved_views: true,
+    restore_time: true,
+    body_meta: BodyMeta::AoC,
+    chapters: true,
+    known_ops: true,
+    sync_checksums: false,
+    postgame: false,
+    chat500_len: 20,
+    json_chat: false,
+    utf8_strings: false,
+    research_player: 8,
+    build_player: 2,
+    resign_by_index: false,
+    hd_map_names: false,
+    spectator_slots: false,
+};
+
+const AOK: VersionProfile = VersionProfile {
+    cheats: false,
+    scenario_version: [0x9a, 0x99, 0x99, 0x3f], // float 1.20
+    string_ids: 5,
+    messages: 5,
+    mapid: false,
+    trigger_skip: 118,
+    lobby: false,
+    static_group: 1,
+    action_skip: 0,
+    unit_type_skip: 0,
+    base_armor: 1,
+    combat_skip: 22,
+    combat_tail: 18,
+    building_tail: 126,
+    player_extra: 0,
+    saved_views: false,
+    body_meta: BodyMeta::AoK,
+    chapters: false,
+    chat500_len: 32,
+    ..AOC
+};
+
+const AOK_TRIAL: VersionProfile = VersionProfile { init_skip: 4, ..AOK };
+
+/// UserPatch and later versions based on AoC have operations unknown to the parser
+const UP: VersionProfile = VersionProfile { known_ops: false, action_state: 4, ..AOC };
+
+const UP15: VersionProfile = VersionProfile { datamod: true, ..UP };
+
+const HD: VersionProfile = VersionProfile {
+    ai_extra: 1280,
+    settings_skip: 2459,
+    static_skip: 3,
+    action_state: 1,
+    action_skip: 4,
+    unit_ai_skip: 4,
+    utf8_strings: true,
+    hd_map_names: true,
+    ..UP
+};
+
+const HD4: VersionProfile = VersionProfile { wide_mapzones: true, ..HD };
+
+const HD5: VersionProfile = VersionProfile { hd_header: true, ..HD4 };
+
+const DE: VersionProfile = VersionProfile {
+    de_header: true,
+    de_tiles: true,
+    restore_time: false,
+    body_meta: BodyMeta::DE,
+    chapters: false,
+    sync_checksums: true,
+    postgame: true,
+    json_chat: true,
+    utf8_strings: true,
+    research_player: 1,
+    build_player: 1,
+    resign_by_index: true,
+    hd_map_names: true,
+    spectator_slots: true,
+    ..UP
+};
+
+impl VersionProfile {
+    /// Profile of `ver`, HD layouts also depend on the save version. Unknown and undetected versions are treated as
+    /// UserPatch
+    pub fn of(ver: Option<&Version>, versave: Option<f32>) -> &'static Self {
+        match ver {
+            Some(Version::AoKTrial) => &AOK_TRIAL,
+            Some(Version::AoK) => &AOK,
+            Some(Version::AoCTrial | Version::AoC | Version::AoC10a | Version::AoC10c) => &AOC,
+            Some(Version::UP15 | Version::MCP) => &UP15,
+            Some(Version::HD) => match versave {
+                Some(x) if x >= HD_LOBBY => &HD5,
+                Some(x) if x >= HD_WIDE_MAPZONES => &HD4,
+                _ => &HD,
+            },
+            Some(Version::DE) => &DE,
+            Some(
+                Version::UP12
+                | Version::UP13
+                | Version::UP14
+                | Version::UP14RC1
+                | Version::UP14RC2
+                | Version::AoFE21
+                | Version::Unknown,
+            )
+            | None => &UP,
+        }
+    }
+}
+
+impl Record {
+    /// Layout profile of the detected version
+    pub(crate) fn profile(&self) -> &'static VersionProfile {
+        VersionProfile::of(self.ver.as_ref(), self.versave)
+    }
+}
+
+/// Reason why the parser rejects this version, `None` if it's supported
+pub(crate) fn unsupported(ver: &Version, versave: f32, versave2: Option<u32>) -> Option<&'static str> {
+    match ver {
+        // Save version of DE is stored in `versave2`, e.g. 63.0 is 0x003f0000
+        Version::DE => match versave2 {
+            Some(x) if versave == -1.0 && DE_SAVES.contains(&(x as f32 / 65536.0)) => None,
+            _ => Some("Only DE save version 63.x is supported"),
+        },
+        _ if !(0.0..DE_SAVE).contains(&versave) => Some("Save version is out of range"),
+        _ => None,
+    }
+}
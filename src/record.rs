@@ -1,19 +1,29 @@
+use crate::{Section, Warning};
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
-/// Get value from `Option<T>` if it's Some and `anyhow::bail!` if `None`
+/// Get value from `Option<T>` if it's Some and return `Error::OutOfBounds` if `None`.
+/// Section and offset of the error are set by the caller
 #[doc(hidden)]
 #[macro_export]
 macro_rules! val {
     ($x:expr) => {
         match $x {
             Some(x) => x,
-            None => bail!("{:?} is None @ {}, line: {}", stringify!($x), file!(), line!()),
+            None => {
+                return Err($crate::Error::OutOfBounds {
+                    section: $crate::Section::Unknown,
+                    offset: 0,
+                    reason: format!("{} is None", stringify!($x)),
+                })
+            }
         }
     };
 }
 
 /// Store information of this game extracted from the recorded game. Most fields will be `None` if not present in the recorded game or exception occurs during parsing
+/// It's `Send`, records can be parsed on other threads, see `parse_many()`
 #[derive(Debug, Serialize, Default)]
 pub struct Record {
     pub parser: String,
@@ -30,6 +40,11 @@ pub struct Record {
     pub versave: Option<f32>,
     pub versave2: Option<u32>,
     pub verscenario: Option<f32>,
+    /// Data mod of UserPatch 1.5 or higher, e.g. WololoKingdoms. `None` for games without data mods
+    pub modid: Option<u32>,
+    /// Also the modded dataset of HD 5.x and DE lobbies
+    pub modname: Option<String>,
+    pub modversion: Option<String>,
     pub include_ai: Option<bool>,
     pub speed_raw: Option<u32>,
     pub speed: Option<String>,
@@ -45,7 +60,12 @@ pub struct Record {
     pub fogofwar: Option<bool>,
     pub instantbuild: Option<bool>,
     pub enablecheats: Option<bool>,
+    /// Game time in milliseconds when the game was saved. `0` if the game is not restored from a saved game
     pub restoretime: Option<u32>,
+    pub isrestored: Option<bool>,
+    /// Saved game(.msx) has no body, `duration` is the time when it was saved. `None` if body is not parsed or the
+    /// file is cut off before body. Only tested with records cut after their header, not with real saved games
+    pub issavedgame: Option<bool>,
     pub ismultiplayer: Option<bool>,
     pub isconquest: Option<bool>,
     pub relics2win: Option<i32>,
@@ -62,10 +82,21 @@ pub struct Record {
     #[serde(skip)]
     pub instructions_raw: Option<Vec<u8>>,
     pub instructions: Option<String>,
+    /// Lobby settings described in instructions. See `parse_instructions()` for keys
+    pub instructionsettings: BTreeMap<String, String>,
+    /// In milliseconds. Time before the restore point is included for restored games
     pub duration: u32,
+    /// Time played after the restore point. Same as `duration` if the game is not restored
+    pub durationafterrestore: u32,
+    /// Game time of save commands and chapters
+    pub savetimes: Vec<u32>,
+    /// Chapters of the body. Records without chapter info have only one chapter
+    pub chapters: Vec<Chapter>,
     pub chat: Vec<Chat>,
     pub mapid: Option<u32>,
     pub mapname: Option<String>,
+    /// Map name after "Map Type" label in instructions. Real name of custom maps
+    pub maptype: Option<String>,
     pub difficulty_raw: Option<i32>,
     pub difficulty: Option<String>,
     pub lockteams: Option<bool>,
@@ -73,10 +104,44 @@ pub struct Record {
     pub gametype_raw: Option<u8>,
     pub gametype: Option<String>,
     pub lockdiplomacy: Option<bool>,
+    /// Lobby resources level. Not stored in AoK/AoC/UP1.5 headers, read from instructions then
+    pub resources_raw: Option<i32>,
+    pub resources: Option<String>,
+    /// Starting age of the lobby, -1 is "Standard". Not stored in AoK/AoC/UP1.5 headers, read from instructions then.
+    /// Initial ages of players are in `Player.initage_raw`
+    pub startingage_raw: Option<i32>,
+    pub startingage: Option<String>,
+    /// "All Techs" of scenario options, read before game settings
+    pub alltechs: Option<bool>,
+    /// Read from instructions, `None` for DE
+    pub fixedpositions: Option<bool>,
+    /// Read from instructions before DE and HD 5.x
+    pub fulltechtree: Option<bool>,
+    /// Only stored in DE and HD 5.x headers, `None` for other versions
+    pub lockspeed: Option<bool>,
+    /// Only stored in DE and HD 5.x headers, `None` for other versions
+    pub allowspectators: Option<bool>,
+    /// Only stored in DE and HD 5.x headers, `None` for other versions
+    pub teamtogether: Option<bool>,
+    /// Only stored in DE and HD 5.x headers, `None` for other versions
+    pub randompositions: Option<bool>,
     pub haswinner: bool,
     pub matchup: Option<Vec<usize>>,
     pub teams: Vec<Vec<i32>>,
+    /// Slots controlling the same player index, main operator first. Spectators are not included
+    pub coops: Vec<Vec<usize>>,
+    /// Slots of spectators. They are still present in `players`. Versions without spectator slots have watchers
+    /// join a player as co-op partners, partners issuing almost no unit commands in body are spectators then
+    pub spectators: Vec<usize>,
+    /// Co-op partners not told from watchers because body is not parsed. They are in neither `coops` nor
+    /// `spectators`
+    pub unknownpartners: Vec<usize>,
     pub players: [Player; 9],
+    /// The file ends in the middle of header or body, e.g. the game crashed or the download was cut short.
+    /// Everything before the cut is kept and `duration` is the last complete game time
+    pub truncated: bool,
+    /// Problems which don't stop parsing, e.g. players without init data
+    pub warnings: Vec<Warning>,
     /// Debug data used by the parser. Strip this out in output json.
     #[serde(skip)]
     pub debug: DebugInfo,
@@ -91,6 +156,10 @@ pub struct Player {
     #[serde(skip)]
     pub name_raw: Option<Vec<u8>>,
     pub name: Option<String>,
+    /// Only presents in DE
+    pub profileid: Option<u32>,
+    /// Only presents in HD 5.x, `None` for AI players
+    pub steamid: Option<u64>,
     pub teamid: Option<u8>,
     pub ismainop: Option<bool>,
     pub initx: Option<f32>,
@@ -112,7 +181,8 @@ pub struct Player {
     pub initpop: Option<f32>,
     pub initcivilian: Option<f32>,
     pub initmilitary: Option<f32>,
-    /// Only presents in UP1.5
+    /// Resource 198 of UP1.5 or higher, e.g. `1581.0`
+    #[deprecated(note = "use `Record.modid` and `Record.modversion`")]
     pub modversion: Option<f32>,
     /// Default is `false`. Only for fair 2-sided games
     pub winner: Option<bool>,
@@ -126,13 +196,36 @@ impl Player {
     pub fn isvalid(&self) -> bool {
         self.playertype.is_some_and(|x| (2..=5).contains(&x))
     }
+
+    /// Spectator slots only exist in UP1.5 or higher versions
+    pub fn isspectator(&self) -> bool {
+        self.playertype == Some(6)
+    }
+}
+
+/// A chapter of the recorded game. Chapters after the first one are created when the game is saved
+#[derive(Debug, Serialize)]
+pub struct Chapter {
+    /// Position of the chapter in the file. `0` for the first chapter
+    pub headerpos: usize,
+    /// Byte range of the chapter body in the file
+    pub bodystart: usize,
+    pub bodyend: usize,
+    /// Game time when the chapter starts
+    pub time: u32,
+    /// Whether the previous chapter has a save command
+    pub fromsave: bool,
+    /// Decompressed chapter header, only kept with `ParseOptions.chapter_headers`. `None` for the first chapter, use
+    /// `Parser.header` instead
+    #[serde(skip)]
+    pub header: Option<Vec<u8>>,
 }
 
-/// Information of a chat message. Lobby chats don't have time. Field `player` is not implemented yet
+/// Information of a chat message. Lobby chats don't have time. Field `player` is only implemented for DE
 #[derive(Debug, Serialize)]
 pub struct Chat {
     pub time: Option<u32>,
-    /// Not implemented yet
+    /// Only presents in DE
     pub player: Option<u8>,
     #[serde(skip)]
     pub content_raw: Option<Vec<u8>>,
@@ -142,6 +235,8 @@ pub struct Chat {
 /// Debug information used by the parser
 #[derive(Debug, Default)]
 pub struct DebugInfo {
+    /// Section being parsed, used to locate errors
+    pub section: Section,
     pub currentpos_header: usize,
     pub currentpos_body: usize,
     pub aipos: usize,
@@ -157,6 +252,13 @@ pub struct DebugInfo {
     pub earlymovecount: usize,
     pub earlymovecmd: Vec<[u8; 19]>,
     pub earlymovetime: Vec<u32>,
+    pub savecmdfound: bool,
+    /// Order and move commands by the slot issuing them. Co-op partners issue their own before DE
+    pub unitcmds: [usize; 9],
+    /// Operations parsed in body, checked against `Limits.body_ops`
+    pub opcount: usize,
+    /// Start of garbage in body when skipping it runs past a window read from `io::Read`
+    pub skipfrom: Option<usize>,
 }
 
 /// Version of the recorded game
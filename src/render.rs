@@ -1,7 +1,8 @@
+use crate::error::{Error, Result, Section};
 use crate::trans;
 use crate::translations::{en, zh};
 use crate::Record;
-use anyhow::Result;
+use crate::Warning;
 use encoding_rs::Encoding;
 use phf::phf_map;
 
@@ -34,6 +35,15 @@ impl Record {
         })
     }
 
+    /// Encoding used to decode in-game strings. HD and DE always use UTF-8, fallback is GBK
+    pub(crate) fn encoding(&self) -> &'static Encoding {
+        if self.profile().utf8_strings {
+            return encoding_rs::UTF_8;
+        }
+        let encoding_name = self.detect_encoding().unwrap_or_else(|| "GBK".to_string());
+        Encoding::for_label(encoding_name.as_bytes()).unwrap_or(encoding_rs::GBK)
+    }
+
     pub fn translate(&mut self, lang: &str) {
         self.gametype = trans!(self.gametype_raw, lang, GAME_TYPES_TRANS);
         self.difficulty = trans!(self.difficulty_raw, lang, DIFFICULTIES_TRANS);
@@ -43,6 +53,11 @@ impl Record {
         self.victorytype = trans!(self.victorytype_raw, lang, VICTORY_TYPE_TRANS);
         self.time2win = trans!(self.time2win_raw, lang, VICTORY_TIME_TRANS);
         self.mapname = trans!(self.mapid, lang, MAP_NAMES_TRANS);
+        if self.profile().hd_map_names {
+            self.mapname = trans!(self.mapid, lang, HD_MAP_NAMES_TRANS).or(self.mapname.take());
+        }
+        self.resources = trans!(self.resources_raw, lang, STARTING_RESOURCES_TRANS);
+        self.startingage = trans!(self.startingage_raw, lang, AGES_TRANS);
         for p in self.players.iter_mut() {
             p.civ = trans!(p.civ_raw, lang, CIVILIZATIONS_TRANS);
             p.initage = trans!(p.initage_raw, lang, AGES_TRANS);
@@ -50,8 +65,13 @@ impl Record {
     }
 
     pub fn convert_encoding(&mut self) {
-        let encoding_name = self.detect_encoding().unwrap_or_else(|| "GBK".to_string());
-        let encoding = Encoding::for_label(encoding_name.as_bytes()).unwrap_or(encoding_rs::GBK);
+        let encoding = self.encoding();
+        if !self.profile().utf8_strings && self.detect_encoding().is_none() {
+            let fallback = Warning::EncodingFallback { encoding: encoding.name() };
+            if !self.warnings.contains(&fallback) {
+                self.warnings.push(fallback);
+            }
+        }
 
         if let Some(x) = self.instructions_raw.as_ref() {
             let (decoded, _, _) = encoding.decode(x);
@@ -75,7 +95,11 @@ impl Record {
 
     pub fn dump_json(&mut self) -> Result<String> {
         self.convert_encoding();
-        serde_json::to_string(self).map_err(Into::into)
+        serde_json::to_string(self).map_err(|e| Error::Serialize {
+            section: Section::Unknown,
+            offset: 0,
+            reason: e.to_string(),
+        })
     }
 }
 
@@ -1,6 +1,7 @@
 use phf::phf_map;
 
 pub static AGES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    -1i32 => "Standard",
     0i32 => "Dark Age",
     1i32 => "Feudal Age",
     2i32 => "Castle Age",
@@ -123,6 +124,12 @@ pub static MAP_NAMES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     105i32 => "The Eye",
 };
 
+/// Map ids of HD and DE that differ from `MAP_NAMES_TRANS`
+pub static HD_MAP_NAMES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    59i32 => "Hamburger",
+    60i32 => "MegaRandom",
+};
+
 pub static _RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     0i32 => "Food",
     1i32 => "Wood",
@@ -130,6 +137,13 @@ pub static _RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     3i32 => "Gold",
 };
 
+pub static STARTING_RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    0i32 => "Standard",
+    1i32 => "Low",
+    2i32 => "Medium",
+    3i32 => "High",
+};
+
 pub static VICTORY_TYPE_TRANS: phf::Map<i32, &'static str> = phf_map! {
     0i32 => "Default",
     1i32 => "Conquest",
@@ -155,6 +169,7 @@ pub static VICTORY_TIME_TRANS: phf::Map<i32, &'static str> = phf_map! {
 pub static GAME_SPEEDS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     100i32 => "Slow",
     150i32 => "Normal",
+    169i32 => "Normal",
     178i32 => "Normal",
     200i32 => "Fast",
     237i32 => "Fast",
@@ -199,23 +214,23 @@ pub static CIVILIZATIONS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     29i32 => "Malay",
     30i32 => "Burmese",
     31i32 => "Vietnamese",
-    32i32 => "ReservedCivA",
-    33i32 => "ReservedCivB",
-    34i32 => "ReservedCivC",
-    35i32 => "ReservedCivD",
-    36i32 => "ReservedCivE",
-    37i32 => "ReservedCivF",
-    38i32 => "ReservedCivG",
-    39i32 => "ReservedCivH",
-    40i32 => "ReservedCivI",
-    41i32 => "ReservedCivJ",
-    42i32 => "ReservedCivK",
-    43i32 => "ReservedCivL",
-    44i32 => "ReservedCivM",
-    45i32 => "ReservedCivN",
-    46i32 => "ReservedCivO",
-    47i32 => "ReservedCivP",
-    48i32 => "ReservedCivQ",
+    32i32 => "Bulgarians",
+    33i32 => "Tatars",
+    34i32 => "Cumans",
+    35i32 => "Lithuanians",
+    36i32 => "Burgundians",
+    37i32 => "Sicilians",
+    38i32 => "Poles",
+    39i32 => "Bohemians",
+    40i32 => "Dravidians",
+    41i32 => "Bengalis",
+    42i32 => "Gurjaras",
+    43i32 => "Romans",
+    44i32 => "Armenians",
+    45i32 => "Georgians",
+    46i32 => "Achaemenids",
+    47i32 => "Athenians",
+    48i32 => "Spartans",
 };
 
 pub static MAP_SIZES_TRANS: phf::Map<i32, &'static str> = phf_map! {
@@ -236,7 +251,7 @@ pub static DIFFICULTIES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     4i32 => "Easiest",
 };
 
-pub static _UNITS_TRANS: phf::Map<i32, &'static str> = phf_map! {
+pub static UNITS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     1i32 => "Legionary",
     4i32 => "Archer",
     5i32 => "Hand Cannoneer",
@@ -1,6 +1,7 @@
 use phf::phf_map;
 
 pub static AGES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    -1i32 => "标准",
     0i32 => "黑暗时代",
     1i32 => "封建时代",
     2i32 => "城堡时代",
@@ -123,6 +124,12 @@ pub static MAP_NAMES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     105i32 => "眼睛",
 };
 
+/// Map ids of HD and DE that differ from `MAP_NAMES_TRANS`
+pub static HD_MAP_NAMES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    59i32 => "汉堡包",
+    60i32 => "超级随机",
+};
+
 pub static _RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     0i32 => "食物",
     1i32 => "木材",
@@ -130,6 +137,13 @@ pub static _RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     3i32 => "黄金",
 };
 
+pub static STARTING_RESOURCES_TRANS: phf::Map<i32, &'static str> = phf_map! {
+    0i32 => "标准",
+    1i32 => "低",
+    2i32 => "中",
+    3i32 => "高",
+};
+
 pub static VICTORY_TYPE_TRANS: phf::Map<i32, &'static str> = phf_map! {
     0i32 => "默认",
     1i32 => "征服",
@@ -155,6 +169,7 @@ pub static VICTORY_TIME_TRANS: phf::Map<i32, &'static str> = phf_map! {
 pub static GAME_SPEEDS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     100i32 => "慢",
     150i32 => "正常",
+    169i32 => "正常",
     178i32 => "正常",
     200i32 => "快",
     237i32 => "快",
@@ -199,6 +214,23 @@ pub static CIVILIZATIONS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     29i32 => "马来",
     30i32 => "缅甸",
     31i32 => "越南",
+    32i32 => "保加利亚",
+    33i32 => "鞑靼",
+    34i32 => "库曼",
+    35i32 => "立陶宛",
+    36i32 => "勃艮第",
+    37i32 => "西西里",
+    38i32 => "波兰",
+    39i32 => "波希米亚",
+    40i32 => "达罗毗荼",
+    41i32 => "孟加拉",
+    42i32 => "古尔贾拉",
+    43i32 => "罗马",
+    44i32 => "亚美尼亚",
+    45i32 => "格鲁吉亚",
+    46i32 => "阿契美尼德",
+    47i32 => "雅典",
+    48i32 => "斯巴达",
 };
 
 pub static MAP_SIZES_TRANS: phf::Map<i32, &'static str> = phf_map! {
@@ -219,7 +251,7 @@ pub static DIFFICULTIES_TRANS: phf::Map<i32, &'static str> = phf_map! {
     4i32 => "最易",
 };
 
-pub static _UNITS_TRANS: phf::Map<i32, &'static str> = phf_map! {
+pub static UNITS_TRANS: phf::Map<i32, &'static str> = phf_map! {
     1i32 => "罗马军",
     4i32 => "步弓手",
     5i32 => "火枪手",
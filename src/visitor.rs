@@ -0,0 +1,40 @@
+use crate::events::CommandKind;
+use crate::profile::VersionProfile;
+use crate::record::{Chat, Record};
+
+/// Callbacks for operations in body, called by `Parser::parse_with()` in the same pass that fills `Record`.
+/// Each callback is called after `r` is updated with the operation, so `r.duration` is the current game time.
+/// All callbacks do nothing by default
+pub trait BodyVisitor {
+    fn on_sync(&mut self, _r: &Record, _delta: u32) {}
+
+    /// `bytes` is the command data starting with the command id. `player` is the index of the player issuing order,
+    /// move, build, research, tribute and resign commands. It's `None` for other commands, their player byte is at an
+    /// unknown offset or missing
+    fn on_command(&mut self, _r: &Record, _player: Option<u8>, _kind: CommandKind, _bytes: &[u8]) {}
+
+    /// In-game chat, the last one in `r.chat`
+    fn on_chat(&mut self, _r: &Record, _chat: &Chat) {}
+
+    /// Player in `r.players[slot]` resigned
+    fn on_resign(&mut self, _r: &Record, _slot: usize) {}
+}
+
+/// Visits nothing, `Parser::parse_to()` uses it
+impl BodyVisitor for () {}
+
+/// Player index of a command, see `BodyVisitor::on_command()`
+pub(crate) fn command_player(kind: CommandKind, bytes: &[u8], profile: &VersionProfile, r: &Record) -> Option<u8> {
+    match kind {
+        // Unit commands carry the slot, co-op partners issue them from their own slots
+        CommandKind::Order | CommandKind::Move => {
+            let slot = *bytes.get(1)? as usize;
+            let player = r.players.get(slot).filter(|p| slot > 0 && p.isvalid())?;
+            player.index.map(|x| x as u8)
+        }
+        CommandKind::Resign | CommandKind::Tribute => bytes.get(1).copied(),
+        CommandKind::Research => bytes.get(profile.research_player).copied(),
+        CommandKind::Build => bytes.get(profile.build_player).copied(),
+        _ => None,
+    }
+}
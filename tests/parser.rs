@@ -1,6 +1,7 @@
 use mgx::from_file;
 use mgx::Version;
 use mgx::draw_map;
+use mgx::{Parser, Record};
 
 #[test]
 fn aok_trial_test() {
@@ -9,6 +10,7 @@ fn aok_trial_test() {
     
     assert_eq!(rec.guid, Some("6603ada367f5c73b9179960c955786ee".to_string()));
     assert_eq!(rec.ver, Some(Version::AoKTrial));
+    assert_eq!(rec.startingage_raw, Some(2));
     assert_eq!(rec.duration, 1933820);
     assert_eq!(rec.matchup, Some(vec![1, 1, 1]));
 
@@ -35,6 +37,13 @@ fn aok_test() {
     rec.translate("en");
     assert_eq!(rec.ver, Some(Version::AoK));
     assert_eq!(rec.speed, Some("Fast".to_string()));
+    assert_eq!(rec.alltechs, Some(false));
+    assert_eq!(rec.startingage_raw, Some(4));
+    assert_eq!(rec.startingage, Some("Post-Imperial Age".to_string()));
+    assert_eq!(rec.resources, Some("Standard".to_string()));
+    assert_eq!(rec.fixedpositions, Some(true));
+    assert_eq!(rec.fulltechtree, Some(false));
+    assert_eq!(rec.enablecheats, Some(false));
     assert_eq!(rec.duration, 9770100);
     assert_eq!(rec.matchup, Some(vec![4, 4]));
     assert_eq!(rec.guid, Some("f94380bd153af62786c7ad2a0e01d114".to_string()));
@@ -127,8 +136,13 @@ fn aoc10c_test() {
 #[test]
 fn aoc10c_ai_test() {
     let filename = "tests/recs/aoc10c_with_AI.mgx";
-    let (rec, parser) = from_file(filename).unwrap();
+    let (mut rec, parser) = from_file(filename).unwrap();
     assert_eq!(rec.include_ai, Some(true));
+    rec.translate("en");
+    assert_eq!(rec.maptype, Some("Arabia".to_string()));
+    assert_eq!(rec.resources, Some("Low".to_string()));
+    assert_eq!(rec.fixedpositions, Some(false));
+    assert_eq!(rec.instructionsettings.get("difficulty"), Some(&"Hard".to_string()));
     
     draw_map(&rec, &parser, &format!("{}.png", filename)).unwrap();
     std::fs::remove_file(format!("{}.png", filename)).unwrap();
@@ -164,6 +178,7 @@ fn up14_scenario_test() {
     let (rec, parser) = from_file(filename).unwrap();
     assert!(rec.verscenario.unwrap() - 1.22 < 0.0001);
     assert_eq!(rec.ver, Some(Version::UP14));
+    assert_eq!(rec.startingage_raw, None);
 
     draw_map(&rec, &parser, &format!("{}.png", filename)).unwrap();
     std::fs::remove_file(format!("{}.png", filename)).unwrap();
@@ -174,6 +189,9 @@ fn up15_test() {
     let filename = "tests/recs/up1.5.mgz";
     let (rec, parser) = from_file(filename).unwrap();    
     assert_eq!(rec.ver, Some(Version::UP15));
+    assert_eq!(rec.startingage_raw, Some(3));
+    assert_eq!(rec.resources_raw, Some(2));
+    assert_eq!(rec.modid, None);
 
     draw_map(&rec, &parser, &format!("{}.png", filename)).unwrap();
     std::fs::remove_file(format!("{}.png", filename)).unwrap();
@@ -193,31 +211,156 @@ fn up15_test() {
 #[test]
 fn hd_test() {
     let filename = "tests/recs/HD-FE.mgx2";
-    match from_file(filename) {
-        Err(e) => {
-            assert!(e.to_string().contains("DE/HD or higher versions are not supported"));
+    let (mut rec, parser) = from_file(filename).unwrap();
+    assert_eq!(rec.ver, Some(Version::HD));
+    assert_eq!(rec.speed_raw, Some(150));
+    assert_eq!(rec.matchup, Some(vec![1, 1]));
+    assert_eq!(rec.duration, 139203);
+    rec.translate("en");
+    rec.convert_encoding();
+    assert_eq!(rec.players[1].name, Some("Idle Beaver".to_string()));
+    assert_eq!(rec.players[1].civ, Some("Slavs".to_string()));
+    assert_eq!(rec.players[2].civ, Some("Italians".to_string()));
+    assert_eq!(rec.players[2].winner, Some(true));
+    assert_eq!(rec.maptype, Some("MegaRandom".to_string()));
+    assert_eq!(rec.mapname, Some("MegaRandom".to_string()));
+    assert_eq!(rec.startingage, Some("Standard".to_string()));
+    assert_eq!(rec.players[1].steamid, None);
+
+    draw_map(&rec, &parser, &format!("{}.png", filename)).unwrap();
+    std::fs::remove_file(format!("{}.png", filename)).unwrap();
+}
+
+fn hd_string(s: &[u8]) -> Vec<u8> {
+    [&(s.len() as u16).to_le_bytes()[..], &[0x60, 0x0a], s].concat()
+}
+
+/// Lobby settings and players HD 5.x stores before AI data, steam ids of slots 1 and 2
+fn hd5_block(version: f32, ratings: bool) -> Vec<u8> {
+    let separator = [0xa3, 0x5f, 0x02, 0x00];
+    let mut block = version.to_le_bytes().to_vec();
+    // Settings differ from those later in the header: difficulty, map id, reveal map, victory type and game type
+    for x in [0u32, 0, 1, 1, 0, 0, 33, 33, 1, 1, 0, 0, 0, 2] {
+        block.extend(x.to_le_bytes());
+    }
+    block.extend(separator.repeat(2));
+    // Speed, population limit, lock teams and cheats differ too
+    block.extend(1.7f32.to_le_bytes());
+    for x in [0u32, 250, 2, 0, 0] {
+        block.extend(x.to_le_bytes());
+    }
+    block.extend(separator);
+    block.extend([1, 0, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 1, 1]);
+    block.extend(separator);
+    for i in 1..9u64 {
+        block.extend([0; 27]);
+        block.extend(hd_string(b""));
+        block.push(0);
+        block.extend(hd_string(b""));
+        block.extend(hd_string(if i < 3 { b"player" } else { b"" }));
+        block.extend(if i < 3 { 2u32 } else { 0 }.to_le_bytes());
+        block.extend(if i < 3 { 76561198000000000 + i } else { 0 }.to_le_bytes());
+        block.extend((i as i32).to_le_bytes());
+        if ratings {
+            block.extend([0; 8]);
         }
-        _ => (),
+    }
+    block.extend([0, 0, 0]);
+    block.extend(separator);
+    block.extend([0, 1]);
+    block.extend([0; 8]);
+    for _ in 0..3 {
+        block.extend(hd_string(b""));
+        block.extend([0; 8]);
+    }
+    block.extend([0; 16]);
+    block.extend(hd_string(b"lobby"));
+    block.extend(hd_string(b""));
+    block.extend([0; 4 + 4]);
+    block.extend(hd_string(b""));
+    block.extend([0; 4]);
+    block
+}
+
+#[test]
+fn hd5_test() {
+    // HD 5.x shares the rest of header with earlier HD versions, put its lobby block into an HD 4.x record
+    for (version, ratings) in [(1005.0, false), (1006.0, true), (1006.0, false)] {
+        let mut parser = Parser::new(std::fs::read("tests/recs/HD-FE.mgx2").unwrap()).unwrap();
+        let header = &mut parser.header.src;
+        header[8..12].copy_from_slice(&12.49f32.to_le_bytes());
+        header.splice(12..12, hd5_block(version, ratings));
+        let mut rec = Record::default();
+        parser.parse_to(&mut rec).unwrap();
+        assert_eq!(rec.ver, Some(Version::HD));
+        assert_eq!(rec.players[1].steamid, Some(76561198000000001));
+        assert_eq!(rec.players[2].steamid, Some(76561198000000002));
+        assert_eq!(rec.players[3].steamid, None);
+        assert_eq!(rec.lockspeed, Some(true));
+        assert_eq!(rec.allowspectators, Some(true));
+        assert_eq!(rec.randompositions, Some(true));
+        assert_eq!(rec.mapid, Some(33));
+        assert_eq!(rec.difficulty_raw, Some(0));
+        assert_eq!(rec.revealmap_raw, Some(1));
+        assert_eq!(rec.victorytype_raw, Some(1));
+        assert_eq!(rec.gametype_raw, Some(2));
+        assert_eq!(rec.speed_raw, Some(170));
+        assert_eq!(rec.poplimit, Some(250));
+        assert_eq!(rec.lockteams, Some(false));
+        assert_eq!(rec.enablecheats, Some(true));
+        assert_eq!(rec.matchup, Some(vec![1, 1]));
+        assert_eq!(rec.duration, 139203);
     }
 }
 
 #[test]
 fn de63_test() {
     let filename = "tests/recs/de-63.0.aoe2record";
-    match from_file(filename) {
-        Err(e) => {
-            assert!(e.to_string().contains("DE/HD or higher versions are not supported"));
-        }
-        _ => (),
-    }
+    let (mut rec, parser) = from_file(filename).unwrap();
+    assert_eq!(rec.ver, Some(Version::DE));
+    assert_eq!(rec.mapid, Some(29));
+    assert_eq!(rec.mapx, Some(168));
+    assert_eq!(rec.mapy, Some(168));
+    assert_eq!(rec.debug.mappos, Some(414447));
+    // DE stores normal speed as f32 1.69
+    assert_eq!(rec.speed_raw, Some(169));
+    assert!(rec.warnings.is_empty());
+    assert_eq!(rec.matchup, Some(vec![2, 2]));
+    assert_eq!(rec.teams, vec![vec![1, 3], vec![2, 4]]);
+    assert_eq!(rec.duration, 1408873);
+    assert_eq!(rec.players[1].profileid, Some(915284));
+    assert_eq!(rec.players[2].resigned, Some(1400054));
+    assert_eq!(rec.players[3].winner, Some(true));
+    rec.translate("en");
+    rec.convert_encoding();
+    assert_eq!(rec.players[1].name, Some("Original AI".to_string()));
+    assert_eq!(rec.players[1].civ, Some("Mayans".to_string()));
+    assert_eq!(rec.startingage, Some("Dark Age".to_string()));
+    assert_eq!(rec.chat.len(), 12);
+    assert_eq!(rec.chat[0].player, Some(4));
+    assert_eq!(rec.chat[0].content, Some("GG".to_string()));
+
+    draw_map(&rec, &parser, &format!("{}.png", filename)).unwrap();
+    std::fs::remove_file(format!("{}.png", filename)).unwrap();
+
+    // Later save versions changed lobby settings and players
+    let mut parser = Parser::new(std::fs::read(filename).unwrap()).unwrap();
+    parser.header.src[12..16].copy_from_slice(&(64u32 << 16).to_le_bytes());
+    let err = parser.parse_to(&mut Record::default()).err().unwrap();
+    assert!(matches!(err, mgx::Error::UnsupportedVersion { section: mgx::Section::Version, .. }));
 }
 
 #[test]
 fn headerlen_missing_test() {
     //! This record has no header length(0x00 0x00 0x00 0x00)
     let filename = "tests/recs/headerlen_is_missing.mgx";
-    let (rec, _) = from_file(filename).unwrap();
+    let (mut rec, _) = from_file(filename).unwrap();
     assert_eq!(rec.ver, Some(Version::AoC10a));
+
+    // Custom map, real name is in instructions
+    rec.translate("en");
+    assert_eq!(rec.mapname, Some("Custom".to_string()));
+    assert_eq!(rec.maptype, Some("- - - -!!!LN（标准）".to_string()));
 }
 
 #[test]
@@ -238,6 +381,170 @@ fn next_chapter_test() {
     let (rec, _) = from_file(filename).unwrap();    
     assert_eq!(rec.ver, Some(Version::AoC10c));
     assert_eq!(rec.duration, 3179880);
+    assert_eq!(rec.durationafterrestore, 3179880);
+    assert_eq!(rec.isrestored, Some(false));
+    assert_eq!(rec.savetimes, vec![619080]);
+    assert_eq!(rec.chapters.len(), 2);
+    assert_eq!(rec.chapters[0].bodyend, 613960);
+    assert_eq!(rec.chapters[1].headerpos, 613960);
+    assert_eq!(rec.chapters[1].time, 619080);
+    assert!(!rec.chapters[1].fromsave);
+    assert!(rec.chapters.iter().all(|c| c.header.is_none()));
+
+    // Chapter headers are only kept on request, by both parsers
+    let options = mgx::ParseOptions { chapter_headers: true, ..Default::default() };
+    let mut parser = Parser::with_options(std::fs::read(filename).unwrap(), options.clone()).unwrap();
+    let mut kept = Record::default();
+    parser.parse_to(&mut kept).unwrap();
+    let mut streamed = Record::default();
+    mgx::from_reader(std::fs::File::open(filename).unwrap(), &mut streamed, options).unwrap();
+    for r in [kept, streamed] {
+        assert!(r.chapters[0].header.is_none());
+        assert!(r.chapters[1].header.as_ref().is_some_and(|h| h.starts_with(b"VER 9.4")));
+    }
+}
+
+#[test]
+fn instructions_header_test() {
+    // Lobby settings in instructions agree with the same settings read from header
+    for filename in [
+        "tests/recs/aok_trial.mgl",
+        "tests/recs/aok_4v4_fast.mgl",
+        "tests/recs/aoc10c_with_AI.mgx",
+        "tests/recs/up1.5.mgz",
+        "tests/recs/up15_with_bad_command.mgz",
+        "tests/recs/HD-FE.mgx2",
+    ] {
+        let (mut rec, _) = from_file(filename).unwrap();
+        rec.translate("en");
+        let settings = rec.instructionsettings.clone();
+        assert_eq!(rec.mapsize.as_ref(), settings.get("mapsize"), "{}", filename);
+        assert_eq!(rec.difficulty.as_ref(), settings.get("difficulty"), "{}", filename);
+        assert_eq!(settings.get("revealmap").map(|x| x == "Yes"), rec.revealmap_raw.map(|x| x != 0), "{}", filename);
+        if settings.contains_key("age") {
+            assert_eq!(rec.startingage.as_ref(), settings.get("age"), "{}", filename);
+        }
+        assert_eq!(settings["poplimit"].parse().ok(), rec.poplimit, "{}", filename);
+        assert!(!rec.warnings.iter().any(|w| matches!(w, mgx::Warning::InstructionsMismatch { .. })), "{}", filename);
+        if settings["age"] != "Standard" {
+            // Players of Post-Imperial Age games start in Imperial Age
+            let startingage = rec.startingage_raw.unwrap().min(3) as f32;
+            assert!(rec.players.iter().filter(|p| p.isvalid()).all(|p| p.initage_raw == Some(startingage)));
+        }
+    }
+}
+
+#[test]
+fn instructions_labels_test() {
+    // "Map Type" of every language that tells the encoding
+    for label in [
+        "Map Type",
+        "地图类型",
+        "地图类别",
+        "地圖類型",
+        "地圖類別",
+        "マップの種類",
+        "マップ種類",
+        "지도 종류",
+        "Tipo de Mapa",
+        "Kartentyp",
+        "Tipo de mapa",
+        "Type de carte",
+        "Tipo di mappa",
+        "Kaarttype",
+        "Тип карты",
+    ] {
+        let settings = mgx::parse_instructions(&format!("{}: Arabia", label));
+        assert_eq!(settings.get("maptype").map(String::as_str), Some("Arabia"), "{}", label);
+    }
+    let settings = mgx::parse_instructions("Kartengröße: Riesig\nZeitalter: Feudalzeit\nFeste Positionen: Ja");
+    assert_eq!(settings.keys().collect::<Vec<_>>(), ["age", "fixedpositions", "mapsize"]);
+}
+
+#[test]
+fn instructions_mismatch_test() {
+    // Header keeps its value if instructions tell another one
+    let mut parser = Parser::new(std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let at = header.windows(21).position(|x| x == b"Population Limit: 200").unwrap();
+    header[at + 18..at + 21].copy_from_slice(b"150");
+    let mut rec = Record::default();
+    parser.parse_to(&mut rec).unwrap();
+    assert_eq!(rec.poplimit, Some(200));
+    assert_eq!(rec.instructionsettings["poplimit"], "150");
+    let mismatch = mgx::Warning::InstructionsMismatch { setting: "poplimit", instructions: "150".into() };
+    assert_eq!(rec.warnings, vec![mismatch]);
+}
+
+#[test]
+fn instructions_values_test() {
+    // Resources and starting age of AoC come from instructions only. Patch them to High and Feudal Age, and cut 3
+    // bytes off the victory text to keep the length
+    let mut parser = Parser::new(std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let from = header.windows(13).position(|x| x == b"by destroying").unwrap();
+    let to = header.windows(14).position(|x| x == b"Resources: Low").unwrap() + 14;
+    let text = String::from_utf8(header[from..to].to_vec()).unwrap();
+    let patched =
+        text.replacen("by destroying", "destroying", 1).replace("Dark Age", "Feudal Age").replace("Low", "High");
+    header.splice(from..to, patched.into_bytes());
+    let mut rec = Record::default();
+    parser.parse_to(&mut rec).unwrap();
+    rec.translate("en");
+    assert_eq!(rec.resources_raw, Some(3));
+    assert_eq!(rec.resources.as_deref(), Some("High"));
+    assert_eq!(rec.startingage_raw, Some(1));
+    assert_eq!(rec.startingage.as_deref(), Some("Feudal Age"));
+    assert!(rec.warnings.is_empty());
+}
+
+#[test]
+fn instructions_unknown_value_test() {
+    // Values unknown to the parser are left out with a warning
+    let mut parser = Parser::new(std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let at = header.windows(14).position(|x| x == b"Resources: Low").unwrap();
+    header[at + 11..at + 14].copy_from_slice(b"Lox");
+    let mut rec = Record::default();
+    parser.parse_to(&mut rec).unwrap();
+    assert_eq!(rec.resources_raw, None);
+    assert_eq!(rec.instructionsettings["resources"], "Lox");
+    let unknown = mgx::Warning::UnknownInstructionValue { setting: "resources", value: "Lox".into() };
+    assert_eq!(rec.warnings, vec![unknown]);
+}
+
+#[test]
+fn restored_game_test() {
+    // Time in body of restored games starts from the restore point. No particles follow the restore time here
+    let filename = "tests/recs/aoc10c_1v1_with_spectator.mgx";
+    let (rec, _) = from_file(filename).unwrap();
+    assert_eq!(rec.isrestored, Some(false));
+    let at = rec.debug.initpos - 12;
+    let mut parser = Parser::new(std::fs::read(filename).unwrap()).unwrap();
+    assert_eq!(parser.header.src[at..at + 8], [0; 8]);
+    parser.header.src[at..at + 4].copy_from_slice(&600000u32.to_le_bytes());
+    let mut restored = Record::default();
+    parser.parse_to(&mut restored).unwrap();
+    assert_eq!(restored.restoretime, Some(600000));
+    assert_eq!(restored.isrestored, Some(true));
+    assert_eq!(restored.issavedgame, Some(false));
+    assert_eq!(restored.duration, 600000 + rec.duration);
+    assert_eq!(restored.durationafterrestore, rec.duration);
+}
+
+#[test]
+fn saved_game_test() {
+    // Saved games(.msx) are taken to share the header layout and have no body. No real saved game is at hand, this is
+    // a record cut after its header, support of real ones is unverified
+    let mut data = std::fs::read("tests/recs/aoc10c_1v1_with_spectator.mgx").unwrap();
+    let headerlen = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+    data.truncate(headerlen);
+    let mut rec = Record::new("saved.msx".to_string(), data.len(), 0);
+    Parser::new(data).unwrap().parse_to(&mut rec).unwrap();
+    assert_eq!(rec.issavedgame, Some(true));
+    assert_eq!(rec.ver, Some(Version::AoC));
+    assert_eq!(rec.duration, 0);
+    assert_eq!(rec.matchup, Some(vec![1, 1]));
 }
 
 #[test]
@@ -292,3 +599,645 @@ fn winner_detection_test() {
     assert!(!rec.haswinner);
     assert_eq!(rec.matchup, Some(vec![3, 3]));
 }
+
+#[test]
+fn spectator_detection_test() {
+    let filename = "tests/recs/aoc10a_team_with_spectators.mgx";
+    let (rec, _) = from_file(filename).unwrap();
+    assert_eq!(rec.spectators, vec![3, 4, 5, 7]);
+    assert_eq!(rec.coops, vec![vec![2, 6]]);
+    assert_eq!(rec.matchup, Some(vec![1, 1]));
+
+    let filename = "tests/recs/aoc10c_1v1_with_spectator.mgx";
+    let (rec, _) = from_file(filename).unwrap();
+    assert_eq!(rec.spectators, vec![3]);
+    assert_eq!(rec.matchup, Some(vec![1, 1]));
+}
+
+#[test]
+fn coop_detection_test() {
+    // Slot 3 joins player 2 and issues commands of its own
+    let (rec, _) = from_file("tests/recs/aoc10a_1v1_with_winner.mgx").unwrap();
+    assert_eq!(rec.coops, vec![vec![2, 3]]);
+    assert!(rec.spectators.is_empty());
+    assert_eq!(rec.matchup, Some(vec![1, 1]));
+
+    // Watchers joined as partners issue a few unit commands, 66 of 1298 of the busiest slot here
+    for (filename, spectator) in [
+        ("tests/recs/aoc10a_3v3_haswinner_resign_test.mgx", 5),
+        ("tests/recs/aoc10a_3v3_with_spectator.mgx", 3),
+        ("tests/recs/aoc10a_3v3_no_winner.mgx", 6),
+    ] {
+        let (rec, _) = from_file(filename).unwrap();
+        assert!(rec.coops.is_empty(), "{}", filename);
+        assert_eq!(rec.spectators, vec![spectator], "{}", filename);
+        assert!(rec.unknownpartners.is_empty(), "{}", filename);
+    }
+
+    // Without body, co-op partners can't be told from watchers
+    let options = mgx::ParseOptions { header_only: true, ..Default::default() };
+    let mut parser =
+        Parser::with_options(std::fs::read("tests/recs/aoc10a_1v1_with_winner.mgx").unwrap(), options).unwrap();
+    let mut rec = Record::default();
+    parser.parse_to(&mut rec).unwrap();
+    assert!(rec.coops.is_empty());
+    assert!(rec.spectators.is_empty());
+    assert_eq!(rec.unknownpartners, vec![3]);
+
+    // DE has spectators out of player slots, slot 3 joins player 1 as a co-op partner
+    let mut parser = Parser::new(std::fs::read("tests/recs/de-63.0.aoe2record").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let name = header.windows(14).position(|x| x == b"\x60\x0a\x0a\x00ButtonBash").unwrap();
+    // Player type, profile id and an unknown u32 are between name and player index
+    let index = name + 14 + 4 + 4 + 4;
+    assert_eq!(header[index..index + 4], 3i32.to_le_bytes());
+    header[index..index + 4].copy_from_slice(&1i32.to_le_bytes());
+    let mut rec = Record::default();
+    parser.parse_to(&mut rec).unwrap();
+    assert_eq!(rec.coops, vec![vec![1, 3]]);
+    assert!(rec.spectators.is_empty());
+    assert_eq!(rec.matchup, Some(vec![1, 2]));
+}
+
+#[test]
+fn probe_test() {
+    for (filename, ver) in [
+        ("tests/recs/aok_trial.mgl", Version::AoKTrial),
+        ("tests/recs/aoc10c_with_AI.mgx", Version::AoC10c),
+        ("tests/recs/headerlen_is_missing.mgx", Version::AoC10a),
+        ("tests/recs/up1.5.mgz", Version::UP15),
+        ("tests/recs/HD-FE.mgx2", Version::HD),
+        ("tests/recs/de-63.0.aoe2record", Version::DE),
+    ] {
+        let probe = mgx::probe(&std::fs::read(filename).unwrap()).unwrap();
+        assert_eq!(probe.ver, ver);
+        assert!(probe.supported);
+        assert_eq!(from_file(filename).unwrap().0.ver, Some(probe.ver));
+    }
+    assert!(mgx::probe(b"not a record").is_err());
+
+    // Position of the next chapter is after the end of a cut file, the header starts at 8 still
+    let mut src = std::fs::read("tests/recs/aoc10a_3v3_with_spectator.mgx").unwrap();
+    src.truncate(500000);
+    assert_eq!(mgx::probe(&src).unwrap().ver, Version::AoC10a);
+
+    // Header length is too small, body starts after the compressed header for both
+    let mut src = std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap();
+    src[..4].copy_from_slice(&100u32.to_le_bytes());
+    assert_eq!(mgx::probe(&src).unwrap().ver, Version::AoC10c);
+    let mut rec = Record::default();
+    Parser::new(src).unwrap().parse_to(&mut rec).unwrap();
+    assert_eq!(rec.ver, Some(Version::AoC10c));
+
+    // Unknown versions are parsed as UserPatch, and supported as the parser accepts them
+    let mut src = std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap();
+    let mut parser = Parser::new(src.clone()).unwrap();
+    parser.header.src[..7].copy_from_slice(b"VER 9.Z");
+    parser.parse_to(&mut Record::default()).unwrap();
+    let mut header = parser.header.src.clone();
+    header.truncate(100);
+    src.truncate(8);
+    let mut encoder = flate2::write::DeflateEncoder::new(&mut src, flate2::Compression::none());
+    std::io::Write::write_all(&mut encoder, &header).unwrap();
+    encoder.finish().unwrap();
+    let probe = mgx::probe(&src).unwrap();
+    assert_eq!(probe.ver, Version::Unknown);
+    assert!(probe.supported);
+}
+
+#[test]
+fn error_test() {
+    let err = Parser::new(b"abc".to_vec()).err().unwrap();
+    assert!(matches!(err, mgx::Error::OutOfBounds { section: mgx::Section::File, offset: 0, .. }));
+
+    let err = Parser::new(b"not a recorded game".to_vec()).err().unwrap();
+    assert!(matches!(err, mgx::Error::Decompression { section: mgx::Section::File, .. }));
+
+    // Trigger section is located by its signature, remove it from the header
+    let mut parser = Parser::new(std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let needle = [0x9a, 0x99, 0x99, 0x99, 0x99, 0x99, 0xf9, 0x3f];
+    while let Some(pos) = header.windows(needle.len()).position(|w| w == needle) {
+        header[pos] = 0;
+    }
+    let headerlen = header.len();
+    let mut rec = Record::default();
+    let err = parser.parse_to(&mut rec).err().unwrap();
+    assert!(matches!(err, mgx::Error::SectionNotFound { section: mgx::Section::Trigger, .. }));
+    assert_eq!(err.offset(), headerlen);
+    assert_eq!(rec.ver, Some(Version::AoC10c));
+
+    // Image of a record without map, and an image that can't be saved
+    let err = mgx::map_image(&Record::default(), &parser).err().unwrap();
+    assert!(matches!(err, mgx::Error::Image { section: mgx::Section::Map, offset: 0, .. }));
+    let err = draw_map(&rec, &parser, "tests/recs/no-such-dir/map.png").err().unwrap();
+    assert!(matches!(err, mgx::Error::Image { section: mgx::Section::Map, .. }));
+}
+
+#[test]
+fn malformed_input_test() {
+    let src = std::fs::read("tests/recs/aoc10c_1v1_with_spectator.mgx").unwrap();
+    for len in [0, 4, 8, 64, 4096, src.len() / 2] {
+        if let Ok(mut parser) = Parser::new(src[..len].to_vec()) {
+            let mut rec = Record::default();
+            let _ = parser.parse_to(&mut rec);
+            let _ = mgx::map_image(&rec, &parser);
+        }
+    }
+
+    // Garbage in the decompressed header
+    let mut parser = Parser::new(src).unwrap();
+    for x in parser.header.src.iter_mut().skip(100).step_by(7) {
+        *x = 0xff;
+    }
+    let mut rec = Record::default();
+    assert!(parser.parse_to(&mut rec).is_err());
+    let _ = mgx::map_image(&rec, &parser);
+}
+
+#[test]
+fn warnings_test() {
+    let (mut rec, _) = from_file("tests/recs/aoc10c_with_AI.mgx").unwrap();
+    rec.convert_encoding();
+    assert!(rec.warnings.is_empty());
+
+    let (mut rec, _) = from_file("tests/recs/scenario-with-messages.mgz").unwrap();
+    rec.convert_encoding();
+    rec.convert_encoding();
+    assert_eq!(rec.warnings, vec![mgx::Warning::ScenarioSearched, mgx::Warning::EncodingFallback { encoding: "GBK" }]);
+
+    let mut parser = Parser::new(std::fs::read("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap()).unwrap();
+    let mut rec = Record::default();
+    let _ = parser.parse_to(&mut rec);
+    assert_eq!(rec.warnings[0], mgx::Warning::SkippedBody { offset: 311295, end: 311311 });
+
+    let options = mgx::ParseOptions { recover: false, ..Default::default() };
+    let src = std::fs::read("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+    let mut parser = Parser::with_options(src, options).unwrap();
+    let mut rec = Record::default();
+    let _ = parser.parse_to(&mut rec);
+    assert_eq!(rec.warnings[0], mgx::Warning::UnusualSyncDelta { offset: 311295, delta: 1128235008 });
+}
+
+#[test]
+fn strict_test() {
+    let src = std::fs::read("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+
+    let mut parser = Parser::new(src.clone()).unwrap();
+    let mut rec = Record::default();
+    assert!(parser.parse_to(&mut rec).is_ok());
+    assert!(!rec.warnings.is_empty());
+
+    let mut parser = Parser::with_options(src, mgx::ParseOptions { strict: true, ..Default::default() }).unwrap();
+    let mut rec = Record::default();
+    let err = parser.parse_to(&mut rec).err().unwrap();
+    assert!(matches!(err, mgx::Error::BadBodyOp { section: mgx::Section::Body, offset: 311295, op: 2 }));
+
+    let src = std::fs::read("tests/recs/aoc10c_with_AI.mgx").unwrap();
+    let mut parser = Parser::with_options(src, mgx::ParseOptions { strict: true, ..Default::default() }).unwrap();
+    assert!(parser.parse_to(&mut Record::default()).is_ok());
+}
+
+#[test]
+fn resync_test() {
+    let (rec, _) = from_file("tests/recs/bugs/zeros_after_body.mgx").unwrap();
+    assert_eq!(rec.duration, 1090260);
+    assert_eq!(rec.chat.len(), 3);
+    assert_eq!(rec.warnings.last(), Some(&mgx::Warning::SkippedBody { offset: 1048620, end: 3094570 }));
+
+    let (rec, _) = from_file("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+    assert_eq!(rec.warnings[0], mgx::Warning::SkippedBody { offset: 311295, end: 311311 });
+    assert_eq!(rec.chat.len(), 22);
+
+    let src = std::fs::read("tests/recs/bugs/AOC10C_1v1_ae44.mgx").unwrap();
+    let mut parser = Parser::with_options(src, mgx::ParseOptions { recover: false, ..Default::default() }).unwrap();
+    let mut misaligned = Record::default();
+    parser.parse_to(&mut misaligned).unwrap();
+    // Pages of 4 KiB from 0x21000 to 0x31000 are overwritten with code, body is followed by other data from 0x61000.
+    // Both ranges are skipped, from the last operation before them
+    let (rec, _) = from_file("tests/recs/bugs/AOC10C_1v1_ae44.mgx").unwrap();
+    assert_eq!(misaligned.duration, 85652);
+    assert_eq!(rec.duration, 691301);
+    assert!(rec.chat.is_empty());
+    let skipped = vec![
+        mgx::Warning::SkippedBody { offset: 135210, end: 200713 },
+        mgx::Warning::SkippedBody { offset: 397306, end: 460614 },
+    ];
+    assert_eq!(rec.warnings, skipped);
+    let file = std::fs::File::open("tests/recs/bugs/AOC10C_1v1_ae44.mgx").unwrap();
+    let mut streamed = Record::default();
+    mgx::from_reader(file, &mut streamed, Default::default()).unwrap();
+    assert_eq!(streamed.duration, rec.duration);
+    assert_eq!(streamed.warnings, skipped);
+    // Garbage read as misaligned operations is one warning for the whole run of unknown ones
+    let unknown = mgx::Warning::UnknownBodyOp { offset: 135210, end: 141098, op: 78312 };
+    assert_eq!(misaligned.warnings[0], unknown);
+}
+
+#[test]
+fn truncated_test() {
+    let src = std::fs::read("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+    let (rec, _) = from_file("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+    assert!(!rec.truncated);
+
+    // Cut in the middle of body
+    let mut parser = Parser::new(src[..1000003].to_vec()).unwrap();
+    let mut cut = Record::default();
+    assert!(parser.parse_to(&mut cut).is_ok());
+    assert!(cut.truncated);
+    assert!(cut.duration > 0 && cut.duration < rec.duration);
+    assert_eq!(cut.chat.len(), 20);
+    assert_eq!(cut.players.iter().filter(|p| p.isvalid()).count(), 8);
+
+    // Cut in the compressed header
+    let mut parser = Parser::new(src[..50000].to_vec()).unwrap();
+    assert!(parser.truncated);
+    let mut cut = Record::default();
+    assert!(parser.parse_to(&mut cut).is_ok());
+    assert!(cut.truncated);
+    assert_eq!(cut.ver, Some(Version::UP15));
+
+    // An unsupported version is reported even if the header is cut
+    let mut header = b"VER 9.4\0".to_vec();
+    header.extend((-1f32).to_le_bytes());
+    header.extend((70u32 << 16).to_le_bytes());
+    header.resize(10000, 0);
+    let mut src = vec![0, 0, 0, 0, 0, 0, 0, 0];
+    let mut encoder = flate2::write::DeflateEncoder::new(&mut src, flate2::Compression::none());
+    std::io::Write::write_all(&mut encoder, &header).unwrap();
+    encoder.finish().unwrap();
+    src.truncate(5000);
+    assert!(!mgx::probe(&src).unwrap().supported);
+    let mut parser = Parser::new(src.clone()).unwrap();
+    assert!(parser.truncated);
+    let err = parser.parse_to(&mut Record::default()).err().unwrap();
+    assert!(matches!(err, mgx::Error::UnsupportedVersion { section: mgx::Section::Version, .. }));
+    assert!(parser.events().is_err());
+    assert!(mgx::from_reader(&src[..], &mut Record::default(), Default::default()).is_err());
+}
+
+#[test]
+fn from_reader_test() {
+    let compare = |src: &[u8]| {
+        let mut expected = Record::default();
+        let parsed = Parser::new(src.to_vec()).and_then(|mut parser| parser.parse_to(&mut expected).map(|_| ()));
+        assert!(parsed.is_ok());
+
+        let mut rec = Record::default();
+        let parser = mgx::from_reader(src, &mut rec, Default::default()).unwrap();
+        assert!(parser.body.data().is_empty());
+        assert_eq!(rec.ver, expected.ver);
+        assert_eq!(rec.duration, expected.duration);
+        assert_eq!(rec.chat.len(), expected.chat.len());
+        assert_eq!(rec.chapters.len(), expected.chapters.len());
+        assert_eq!(rec.warnings, expected.warnings);
+        assert_eq!(rec.truncated, expected.truncated);
+        assert_eq!(rec.guid, expected.guid);
+        assert_eq!(rec.md5, expected.md5);
+        assert_eq!(rec.filesize, src.len());
+    };
+
+    compare(&std::fs::read("tests/recs/up1.5.mgz").unwrap());
+    compare(&std::fs::read("tests/recs/next_chapter_1.mgx").unwrap());
+    let src = std::fs::read("tests/recs/bad_sync_data_at_7272_of_body.mgz").unwrap();
+    compare(&src);
+    compare(&src[..1000003]);
+
+    // A header inflating to nothing after the version string is not read on and on
+    let mut src = vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+    let version = b"VER 9.4\0\0\0\x80\x41";
+    src.extend([0, version.len() as u8, 0, !version.len() as u8, 0xff]);
+    src.extend(version);
+    for _ in 0..(3 << 20) / 5 {
+        src.extend([0, 0, 0, 0xff, 0xff]);
+    }
+    let options = mgx::ParseOptions { limits: mgx::Limits { header: 1000, ..Default::default() }, ..Default::default() };
+    let err = mgx::from_reader(&src[..], &mut Record::default(), options).err().unwrap();
+    assert!(matches!(err, mgx::Error::LimitExceeded { section: mgx::Section::File, limit: "header size", max: 1000, .. }));
+
+    // Both parsers reject a chapter starting before its header
+    let mut src = std::fs::read("tests/recs/next_chapter_1.mgx").unwrap();
+    src[613960..613964].copy_from_slice(&100u32.to_le_bytes());
+    let err = Parser::new(src.clone()).unwrap().parse_to(&mut Record::default()).err().unwrap();
+    assert!(matches!(err, mgx::Error::OutOfBounds { section: mgx::Section::Body, offset: 613960, .. }));
+    let err = mgx::from_reader(&src[..], &mut Record::default(), Default::default()).err().unwrap();
+    assert!(matches!(err, mgx::Error::OutOfBounds { section: mgx::Section::Body, offset: 613960, .. }));
+}
+
+#[test]
+fn header_only_test() {
+    for filename in ["tests/recs/up1.5.mgz", "tests/recs/de-63.0.aoe2record"] {
+        let (full, _) = from_file(filename).unwrap();
+        let options = mgx::ParseOptions { header_only: true, ..Default::default() };
+        let mut parser = Parser::with_options(std::fs::read(filename).unwrap(), options).unwrap();
+        let mut rec = Record::default();
+        parser.parse_to(&mut rec).unwrap();
+
+        assert_eq!(rec.ver, full.ver);
+        assert_eq!(rec.mapid, full.mapid);
+        assert_eq!(rec.teams, full.teams);
+        assert_eq!(rec.matchup, full.matchup);
+        for (p, q) in rec.players.iter().zip(full.players.iter()) {
+            assert_eq!(p.name_raw, q.name_raw);
+            assert_eq!(p.civ_raw, q.civ_raw);
+            assert_eq!(p.resigned, None);
+        }
+        assert_eq!(rec.duration, 0);
+        assert!(rec.chapters.is_empty());
+        assert!(rec.chat.iter().all(|c| c.time.is_none()));
+        assert_eq!(rec.guid, None);
+    }
+}
+
+#[test]
+fn datamod_test() {
+    let src = std::fs::read("tests/recs/up1.5.mgz").unwrap();
+    let (rec, _) = from_file("tests/recs/up1.5.mgz").unwrap();
+
+    // Data mod is resource 198 of player init data, after the player name and 6 bytes. Patch it to WololoKingdoms
+    // 5.8.1 for all players
+    let mut parser = Parser::new(src).unwrap();
+    for pos in rec.debug.playerinitpos_by_idx.iter().flatten() {
+        let header = &mut parser.header.src;
+        let namelen = u16::from_le_bytes([header[*pos], header[pos + 1]]) as usize;
+        let at = pos + 2 + namelen + 6 + 198 * 4;
+        header[at..at + 4].copy_from_slice(&1581.0f32.to_le_bytes());
+    }
+    let mut patched = Record::default();
+    parser.parse_to(&mut patched).unwrap();
+    assert_eq!(patched.modid, Some(1));
+    assert_eq!(patched.modname.as_deref(), Some("WololoKingdoms"));
+    assert_eq!(patched.modversion.as_deref(), Some("5.8.1"));
+    assert_eq!(patched.players[1].initfood, rec.players[1].initfood);
+    #[allow(deprecated)]
+    {
+        assert_eq!(patched.players[1].modversion, Some(1581.0));
+    }
+    // WololoKingdoms has the civ and unit ids of HD/DE
+    patched.players[1].civ_raw = Some(19);
+    patched.translate("en");
+    assert_eq!(patched.players[1].civ.as_deref(), Some("Italians"));
+    assert_eq!(patched.unit_name(4, "en"), Some("Archer"));
+
+    // Modded dataset of DE is a string 214 bytes before the map marker, when strings after it are empty
+    let mut parser = Parser::new(std::fs::read("tests/recs/de-63.0.aoe2record").unwrap()).unwrap();
+    let header = &mut parser.header.src;
+    let dataset = header.windows(4).position(|x| x == [0xad, 0xde, 0xad, 0xde]).unwrap() - 214;
+    assert_eq!(header[dataset..dataset + 4], [0x60, 0x0a, 0, 0]);
+    header[dataset + 2] = 8;
+    header.splice(dataset + 4..dataset + 4, *b"Test Mod");
+    let mut modded = Record::default();
+    parser.parse_to(&mut modded).unwrap();
+    assert_eq!(modded.modname.as_deref(), Some("Test Mod"));
+    assert!(modded.debug.mappos.is_some());
+}
+
+#[test]
+fn header_walk_test() {
+    let options = mgx::ParseOptions { header_only: true, ..Default::default() };
+    for (filename, warnings) in [
+        ("tests/recs/scenario-with-messages.mgz", vec![mgx::Warning::ScenarioSearched]),
+        ("tests/recs/aok_trial.mgl", vec![]),
+        ("tests/recs/aok_4v4_fast.mgl", vec![]),
+        ("tests/recs/aoc10a_303_3p.mgx", vec![]),
+        ("tests/recs/up1.5.mgz", vec![]),
+        ("tests/recs/HD-FE.mgx2", vec![]),
+    ] {
+        let src = std::fs::read(filename).unwrap();
+        let mut parser = Parser::with_options(src.clone(), options.clone()).unwrap();
+        let mut walked = Record::default();
+        parser.parse_to(&mut walked).unwrap();
+        assert_eq!(walked.warnings, warnings);
+        assert_eq!(walked.debug.settingspos - walked.debug.victorypos, 44 + 12544 + 5456);
+
+        // Break the separator before player resources in scenario, sections are searched for then
+        let mut parser = Parser::with_options(src, options.clone()).unwrap();
+        parser.header.src[walked.debug.victorypos - 16 * 6 * 4 - 4] = 0;
+        let mut searched = Record::default();
+        parser.parse_to(&mut searched).unwrap();
+        assert_eq!(searched.warnings, vec![mgx::Warning::HeaderSearched]);
+        assert_eq!(searched.debug.scenariopos, walked.debug.scenariopos);
+        assert_eq!(searched.debug.triggerpos, walked.debug.triggerpos);
+        assert_eq!(searched.debug.playerinitpos_by_idx, walked.debug.playerinitpos_by_idx);
+        assert_eq!(searched.instructions_raw, walked.instructions_raw);
+        assert_eq!(searched.victorytype_raw, walked.victorytype_raw);
+
+        // Break the end of object lists of GAIA, player init data is searched for by names then
+        let player1 = walked.debug.playerinitpos_by_idx[1].unwrap() - (2 + walked.totalplayers.unwrap() as usize + 41);
+        let mut parser = Parser::with_options(std::fs::read(filename).unwrap(), options.clone()).unwrap();
+        let gaia_end = (0..player1).rev().find(|&i| parser.header.src[i] == 0x0b).unwrap();
+        parser.header.src[gaia_end] = 0;
+        let mut searched = Record::default();
+        parser.parse_to(&mut searched).unwrap();
+        assert_eq!(searched.warnings, vec![mgx::Warning::HeaderSearched]);
+        assert_eq!(searched.debug.playerinitpos_by_idx, walked.debug.playerinitpos_by_idx);
+        assert_eq!(searched.debug.triggerpos, walked.debug.triggerpos);
+
+        // Bytes that used to end object lists, put in the position of the first object of GAIA, don't end them
+        let mut parser = Parser::with_options(std::fs::read(filename).unwrap(), options.clone()).unwrap();
+        let src = &mut parser.header.src;
+        let header = [11, 0, 8, 0, 0, 0, 2, 0, 0];
+        let objects = (walked.debug.initpos..src.len()).find(|&i| src[i..].starts_with(&header)).unwrap();
+        let position = objects + 9 + 2 + 2 + 2 + 4 + 4 + 4 + 4 + 1;
+        src[position..position + 12].copy_from_slice(&[0, 11, 0, 2, 0, 0, 0, 2, 0, 0, 0, 11]);
+        let mut planted = Record::default();
+        parser.parse_to(&mut planted).unwrap();
+        assert_eq!(planted.warnings, walked.warnings);
+        assert_eq!(planted.debug.playerinitpos_by_idx, walked.debug.playerinitpos_by_idx);
+        assert_eq!(planted.debug.triggerpos, walked.debug.triggerpos);
+    }
+}
+
+#[test]
+fn events_test() {
+    // A partial operation at the end of the first chapter, positions of chapters after it are moved
+    let mut partial = std::fs::read("tests/recs/next_chapter_1.mgx").unwrap();
+    let end = u32::from_le_bytes(partial[4..8].try_into().unwrap()) as usize;
+    let op = [1, 0, 0, 0, 32, 0, 0, 0, 0x65];
+    partial.splice(end..end, op);
+    for pos in [4, end + op.len(), end + op.len() + 4] {
+        let value = u32::from_le_bytes(partial[pos..pos + 4].try_into().unwrap());
+        if value != 0 {
+            partial[pos..pos + 4].copy_from_slice(&(value + op.len() as u32).to_le_bytes());
+        }
+    }
+    let (whole, _) = from_file("tests/recs/next_chapter_1.mgx").unwrap();
+
+    for src in [
+        std::fs::read("tests/recs/next_chapter_1.mgx").unwrap(),
+        std::fs::read("tests/recs/de-63.0.aoe2record").unwrap(),
+        partial.clone(),
+    ] {
+        let is_partial = src == partial;
+        let mut parser = Parser::new(src).unwrap();
+        let mut rec = Record::default();
+        parser.parse_to(&mut rec).unwrap();
+        if is_partial {
+            assert_eq!(rec.chapters.len(), 2);
+            assert_eq!(rec.duration, whole.duration);
+        }
+        let (mut time, mut chats, mut saves, mut resigns) = (0, 0, Vec::new(), 0);
+        for event in parser.events().unwrap() {
+            match event.unwrap() {
+                mgx::BodyEvent::Sync { time: t, .. } => time = t,
+                mgx::BodyEvent::Chat(chat) => {
+                    assert_eq!(chat.time, Some(time));
+                    chats += 1;
+                }
+                mgx::BodyEvent::Command { kind, bytes, .. } => {
+                    assert_eq!(kind, bytes[0].into());
+                    match kind {
+                        mgx::CommandKind::Save | mgx::CommandKind::Chapter if saves.last() != Some(&time) => {
+                            saves.push(time)
+                        }
+                        mgx::CommandKind::Resign => resigns += 1,
+                        _ => {}
+                    }
+                }
+                mgx::BodyEvent::ViewLock { .. } => {}
+            }
+        }
+        assert_eq!(time, rec.duration);
+        assert_eq!(chats, rec.chat.iter().filter(|c| c.time.is_some()).count());
+        assert_eq!(saves, rec.savetimes);
+        assert!(resigns >= rec.players.iter().filter(|p| p.resigned.is_some()).count());
+    }
+}
+
+#[test]
+fn visitor_test() {
+    #[derive(Default)]
+    struct Counter {
+        time: u32,
+        commands: usize,
+        chats: usize,
+        resigned: Vec<(usize, u32)>,
+    }
+    impl mgx::BodyVisitor for Counter {
+        fn on_sync(&mut self, r: &Record, delta: u32) {
+            assert_eq!(r.duration, self.time + delta);
+            self.time = r.duration;
+        }
+        fn on_command(&mut self, _: &Record, _: Option<u8>, kind: mgx::CommandKind, bytes: &[u8]) {
+            assert_eq!(kind, bytes[0].into());
+            self.commands += 1;
+        }
+        fn on_chat(&mut self, r: &Record, chat: &mgx::Chat) {
+            assert_eq!(chat.time, Some(r.duration));
+            self.chats += 1;
+        }
+        fn on_resign(&mut self, r: &Record, slot: usize) {
+            self.resigned.push((slot, r.duration));
+        }
+    }
+
+    let filename = "tests/recs/aoc10a_3v3_haswinner_resign_test.mgx";
+    let (expected, _) = from_file(filename).unwrap();
+    let mut parser = Parser::new(std::fs::read(filename).unwrap()).unwrap();
+    let mut rec = Record::default();
+    let mut counter = Counter { time: expected.restoretime.unwrap(), ..Default::default() };
+    parser.parse_with(&mut rec, &mut counter).unwrap();
+
+    assert_eq!(rec.duration, expected.duration);
+    assert_eq!(counter.time, rec.duration);
+    assert!(counter.commands > 0);
+    assert_eq!(counter.chats, rec.chat.iter().filter(|c| c.time.is_some()).count());
+    assert!(!counter.resigned.is_empty());
+    for (slot, time) in counter.resigned {
+        assert_eq!(rec.players[slot].resigned, Some(time));
+    }
+}
+
+#[test]
+fn command_player_test() {
+    #[derive(Default)]
+    struct Players(Vec<(mgx::CommandKind, u8, Option<u8>)>);
+    impl mgx::BodyVisitor for Players {
+        fn on_command(&mut self, _: &Record, player: Option<u8>, kind: mgx::CommandKind, bytes: &[u8]) {
+            self.0.push((kind, bytes[1], player));
+        }
+    }
+
+    // Slot 3 is the co-op partner of player 2
+    let filename = "tests/recs/aoc10a_1v1_with_winner.mgx";
+    let mut parser = Parser::new(std::fs::read(filename).unwrap()).unwrap();
+    let mut players = Players::default();
+    parser.parse_with(&mut Record::default(), &mut players).unwrap();
+    for (kind, byte1, player) in players.0 {
+        match kind {
+            mgx::CommandKind::Order | mgx::CommandKind::Move => {
+                assert_eq!(player, Some(if byte1 == 3 { 2 } else { byte1 }))
+            }
+            mgx::CommandKind::Resign | mgx::CommandKind::Build | mgx::CommandKind::Research => {
+                assert!(matches!(player, Some(1 | 2)))
+            }
+            mgx::CommandKind::Tribute => assert!(player.is_some()),
+            _ => assert_eq!(player, None),
+        }
+    }
+}
+
+#[test]
+fn parse_many_test() {
+    let paths = ["tests/recs/up1.5.mgz", "tests/recs/not_exist.mgx", "tests/recs/aok_trial.mgl", "tests/recs/bugs/A_fa80.mgx"];
+    let (records, stats) = mgx::parse_many(&paths, Default::default());
+    assert_eq!(records.len(), paths.len());
+    assert_eq!(records[0].as_ref().unwrap().filename, "up1.5.mgz");
+    assert_eq!(records[0].as_ref().unwrap().guid, from_file(paths[0]).unwrap().0.guid);
+    assert!(matches!(records[1], Err(mgx::Error::Io { .. })));
+    assert_eq!(records[2].as_ref().unwrap().ver, Some(Version::AoKTrial));
+    assert!(matches!(records[3], Err(mgx::Error::SectionNotFound { section: mgx::Section::Trigger, .. })));
+
+    assert_eq!(stats.files, 4);
+    assert_eq!(stats.failed, 2);
+    assert_eq!(stats.panicked, 0);
+    assert_eq!(stats.errors.get(&mgx::Section::Trigger), Some(&1));
+    assert!(stats.threads >= 1 && stats.busy >= stats.slowest.unwrap().1);
+}
+
+#[test]
+fn from_file_mmap_test() {
+    for filename in ["tests/recs/next_chapter_1.mgx", "tests/recs/de-63.0.aoe2record"] {
+        let (expected, _) = from_file(filename).unwrap();
+        let (rec, parser) = mgx::from_file_mmap(filename).unwrap();
+        assert_eq!(parser.body.src.len(), rec.filesize);
+        assert_eq!(rec.filename, expected.filename);
+        assert_eq!(rec.md5, expected.md5);
+        assert_eq!(rec.guid, expected.guid);
+        assert_eq!(rec.duration, expected.duration);
+        assert_eq!(rec.chat.len(), expected.chat.len());
+    }
+    assert!(matches!(mgx::from_file_mmap("tests/recs/not_exist.mgx"), Err(mgx::Error::Io { .. })));
+}
+
+#[test]
+fn limits_test() {
+    let parse = |path: &str, limits: mgx::Limits| {
+        let src = std::fs::read(path).unwrap();
+        let options = mgx::ParseOptions { limits, ..Default::default() };
+        Parser::with_options(src, options).and_then(|mut parser| parser.parse_to(&mut Record::default()).map(|_| ()))
+    };
+
+    let err = parse("tests/recs/up1.5.mgz", mgx::Limits { header: 1000, ..Default::default() }).unwrap_err();
+    assert!(matches!(err, mgx::Error::LimitExceeded { section: mgx::Section::File, limit: "header size", max: 1000, .. }));
+
+    let err = parse("tests/recs/aoc10c_with_AI.mgx", mgx::Limits { ai_strings: 0, ..Default::default() }).unwrap_err();
+    assert!(matches!(err, mgx::Error::LimitExceeded { section: mgx::Section::Ai, limit: "AI strings", .. }));
+
+    let err = parse("tests/recs/up1.5.mgz", mgx::Limits { body_ops: 100, ..Default::default() }).unwrap_err();
+    assert!(matches!(err, mgx::Error::LimitExceeded { section: mgx::Section::Body, limit: "body operations", .. }));
+
+    assert!(parse("tests/recs/up1.5.mgz", mgx::Limits::default()).is_ok());
+
+    // Header buffer doesn't grow past the limit, even when it's compressed well
+    let mut header = b"VER 9.4\0\0\0\x80\x41".to_vec();
+    header.resize(1000000, 0);
+    let mut src = vec![0, 0, 0, 0, 0, 0, 0, 0];
+    let mut encoder = flate2::write::DeflateEncoder::new(&mut src, flate2::Compression::best());
+    std::io::Write::write_all(&mut encoder, &header).unwrap();
+    encoder.finish().unwrap();
+    let options = mgx::ParseOptions { limits: mgx::Limits { header: 1000100, ..Default::default() }, ..Default::default() };
+    let parser = Parser::with_options(src, options).unwrap();
+    assert_eq!(parser.header.src, header);
+    assert!(parser.header.src.capacity() <= 1000101);
+}